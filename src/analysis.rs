@@ -0,0 +1,229 @@
+use crate::position::Position;
+use crate::rules::{Play, dice_from_roll};
+use crate::search::{self, Evaluator, SearchConfig};
+
+// Post-game analysis: every play is compared with the engine's best play for the same roll
+// and labelled by the equity it gave up.
+
+const INACCURACY: f64 = 0.04;
+const ERROR: f64 = 0.08;
+const BLUNDER: f64 = 0.16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mistake {
+    Inaccuracy,
+    Error,
+    Blunder,
+}
+
+impl Mistake {
+    pub fn from_loss(loss: f64) -> Option<Self> {
+        if loss >= BLUNDER {
+            Some(Mistake::Blunder)
+        } else if loss >= ERROR {
+            Some(Mistake::Error)
+        } else if loss >= INACCURACY {
+            Some(Mistake::Inaccuracy)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mistake::Inaccuracy => "inaccuracy",
+            Mistake::Error => "error",
+            Mistake::Blunder => "blunder",
+        }
+    }
+}
+
+// one turn of a game, `position` is before the roll with the mover on turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turn {
+    pub position: Position,
+    pub dice: (u8, u8),
+    pub play: Play,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnAnalysis {
+    pub color: u8,
+    pub best_play: Play,
+    pub best_equity: f64,
+    pub played_equity: f64,
+    pub is_forced: bool, // every legal play ends in the same position
+}
+
+impl TurnAnalysis {
+    pub fn loss(&self) -> f64 {
+        (self.best_equity - self.played_equity).max(0.0)
+    }
+
+    pub fn mistake(&self) -> Option<Mistake> {
+        Mistake::from_loss(self.loss())
+    }
+}
+
+// mistake counts and the average loss per unforced play in thousandths of a point
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Summary {
+    pub inaccuracies: u32,
+    pub errors: u32,
+    pub blunders: u32,
+    pub error_rate: f64,
+}
+
+pub fn analyze_turn(turn: &Turn, config: &SearchConfig, evaluator: &dyn Evaluator) -> TurnAnalysis {
+    let color = turn.position.turn;
+    let dice = dice_from_roll(turn.dice.0, turn.dice.1);
+    let ranked = search::rank_plays(&turn.position, &dice, config, evaluator);
+    let result = |play: &Play| {
+        let mut next = turn.position;
+        next.apply_play(play, &dice);
+        next
+    };
+    let Some(best) = ranked.first() else {
+        return TurnAnalysis {
+            color,
+            best_play: Vec::new(),
+            best_equity: 0.0,
+            played_equity: 0.0,
+            is_forced: true,
+        };
+    };
+    // the ranking only searched its best plays deeper, anything else is searched here
+    let played = result(&turn.play);
+    let played_equity = ranked
+        .iter()
+        .find(|ranked_play| ranked_play.depth == best.depth && result(&ranked_play.play) == played)
+        .map_or_else(
+            || search::play_equity(&turn.position, &turn.play, &dice, config, evaluator),
+            |ranked_play| ranked_play.equity,
+        );
+    TurnAnalysis {
+        color,
+        best_play: best.play.clone(),
+        best_equity: best.equity,
+        played_equity,
+        is_forced: ranked.len() <= 1,
+    }
+}
+
+pub fn analyze_game(
+    turns: &[Turn],
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+) -> Vec<TurnAnalysis> {
+    turns
+        .iter()
+        .map(|turn| analyze_turn(turn, config, evaluator))
+        .collect()
+}
+
+pub fn summarize(analysis: &[TurnAnalysis], color: u8) -> Summary {
+    let mut summary = Summary::default();
+    let mut unforced = 0;
+    let mut total_loss = 0.0;
+    for turn in analysis
+        .iter()
+        .filter(|turn| turn.color == color && !turn.is_forced)
+    {
+        unforced += 1;
+        total_loss += turn.loss();
+        match turn.mistake() {
+            Some(Mistake::Inaccuracy) => summary.inaccuracies += 1,
+            Some(Mistake::Error) => summary.errors += 1,
+            Some(Mistake::Blunder) => summary.blunders += 1,
+            None => {}
+        }
+    }
+    if unforced > 0 {
+        summary.error_rate = total_loss / unforced as f64 * 1000.0;
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{BLACK, WHITE};
+    use crate::search::Heuristic;
+
+    const ONE_PLY: SearchConfig = SearchConfig { depth: 1, width: 4 };
+
+    #[test]
+    fn labels_by_equity_loss() {
+        assert_eq!(Mistake::from_loss(0.01), None);
+        assert_eq!(Mistake::from_loss(0.05), Some(Mistake::Inaccuracy));
+        assert_eq!(Mistake::from_loss(0.1), Some(Mistake::Error));
+        assert_eq!(Mistake::from_loss(0.5), Some(Mistake::Blunder));
+    }
+
+    #[test]
+    fn missing_the_gammon_is_a_blunder() {
+        let position = Position::from_checkers(
+            &[(2, WHITE, 1), (1, WHITE, 1), (12, BLACK, 15)],
+            WHITE,
+            [0, 0],
+            [13, 0],
+        );
+        let best = Turn {
+            position,
+            dice: (2, 1),
+            play: vec![(2, 0), (1, 0)],
+        };
+        let analysis = analyze_turn(&best, &ONE_PLY, &Heuristic);
+        assert!(!analysis.is_forced);
+        assert_eq!(analysis.best_equity, 2.0);
+        assert_eq!(analysis.mistake(), None);
+
+        let wasted = Turn {
+            play: vec![(2, 1), (1, 0)],
+            ..best
+        };
+        let analysis = analyze_turn(&wasted, &ONE_PLY, &Heuristic);
+        assert_eq!(analysis.mistake(), Some(Mistake::Blunder));
+    }
+
+    #[test]
+    fn blocked_checker_is_forced() {
+        let mut checkers: Vec<(usize, u8, u8)> = (1..=6).map(|field| (field, WHITE, 2)).collect();
+        checkers.push((24, WHITE, 3));
+        checkers.push((19, BLACK, 14));
+        let position = Position::from_checkers(&checkers, BLACK, [0, 1], [0, 0]);
+        let turn = Turn {
+            position,
+            dice: (6, 5),
+            play: Vec::new(),
+        };
+        let analysis = analyze_turn(&turn, &ONE_PLY, &Heuristic);
+        assert!(analysis.is_forced);
+        assert_eq!(analysis.loss(), 0.0);
+    }
+
+    #[test]
+    fn summary_skips_forced_plays() {
+        let turn = |color, played_equity, is_forced| TurnAnalysis {
+            color,
+            best_play: Vec::new(),
+            best_equity: 0.5,
+            played_equity,
+            is_forced,
+        };
+        let analysis = [
+            turn(WHITE, 0.3, false),
+            turn(WHITE, 0.5, false),
+            turn(WHITE, 0.0, true),
+            turn(BLACK, 0.45, false),
+        ];
+        let white = summarize(&analysis, WHITE);
+        assert_eq!(
+            (white.blunders, white.errors, white.inaccuracies),
+            (1, 0, 0)
+        );
+        assert!((white.error_rate - 100.0).abs() < 1e-9);
+        let black = summarize(&analysis, BLACK);
+        assert_eq!(black.inaccuracies, 1);
+    }
+}
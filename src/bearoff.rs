@@ -0,0 +1,628 @@
+use crate::bot::all_rolls;
+use crate::position::{Position, WHITE, opponent};
+use crate::rules::{Play, dice_from_roll};
+use crate::search::{Evaluator, Probabilities};
+use std::{
+    fs::{self, read},
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+// Bear-off database. The one-sided part holds, for every way to spread up to `max_checkers`
+// checkers over the 6 home points, the chance to be off in exactly n rolls when bearing off
+// as fast as possible. The two-sided part holds, for every pair of positions with up to
+// `two_sided_checkers` checkers a side, the exact cubeless chance that the side on roll wins
+// when both play to win. Races the two-sided part doesn't cover combine the two one-sided
+// distributions, a close approximation since each side plays for the fewest rolls whatever
+// the other does.
+
+pub const DEFAULT_PATH: &str = "saves/bearoff.db";
+pub const MAX_CHECKERS: u8 = 15;
+pub const TWO_SIDED_CHECKERS: u8 = 6; // 924 positions a side, like gnubg's default
+const MAX_ROLLS: usize = 32; // 15 checkers on the 6 point need at most 30 rolls
+const MAGIC: &[u8; 4] = b"BOFF";
+const TWO_SIDED_MAGIC: &[u8; 4] = b"BTWO";
+const AVERAGE_ROLL: f64 = 49.0 / 6.0; // pips
+
+// checkers per home point, index 0 is the point next to the tray
+pub type Home = [u8; 6];
+
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |result, i| result * (n - i) / (i + 1))
+}
+
+fn pips(home: &Home) -> u32 {
+    home.iter()
+        .enumerate()
+        .map(|(point, &count)| (point as u32 + 1) * count as u32)
+        .sum()
+}
+
+// every position reachable with one die, bearing off like the rules do
+fn single_moves(home: &Home, die: u8) -> Vec<Home> {
+    let die = die as usize;
+    let highest = home.iter().rposition(|&count| count > 0);
+    let mut moves = Vec::new();
+    for point in 1..=6 {
+        if home[point - 1] == 0 {
+            continue;
+        }
+        let mut next = *home;
+        next[point - 1] -= 1;
+        if point > die {
+            next[point - 1 - die] += 1;
+        } else if point < die && Some(point - 1) != highest {
+            continue;
+        }
+        moves.push(next);
+    }
+    moves
+}
+
+// positions after using the whole roll, in both orders for non-doubles
+fn successors(home: &Home, dice: &[u8]) -> Vec<Home> {
+    let mut result = Vec::new();
+    let orders: Vec<Vec<u8>> = if dice.len() == 2 && dice[0] != dice[1] {
+        vec![dice.to_vec(), vec![dice[1], dice[0]]]
+    } else {
+        vec![dice.to_vec()]
+    };
+    for order in orders {
+        let mut current = vec![*home];
+        for &die in &order {
+            let mut next = Vec::new();
+            for home in &current {
+                if home.iter().all(|&count| count == 0) {
+                    next.push(*home);
+                } else {
+                    next.extend(single_moves(home, die));
+                }
+            }
+            next.sort();
+            next.dedup();
+            current = next;
+        }
+        result.extend(current);
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BearoffDatabase {
+    max_checkers: u8,
+    distributions: Vec<[f64; MAX_ROLLS]>, // chance to be off in exactly n rolls, by index
+    two_sided_checkers: u8,
+    two_sided: Vec<f64>, // chance the side on roll wins, by its index * size + other's index
+}
+
+impl BearoffDatabase {
+    // position of `home` among all homes with up to `max_checkers` checkers: the counts
+    // with a separator after each point form a subset of separator places, ranked by the
+    // combinatorial number system
+    pub fn index(&self, home: &Home) -> usize {
+        let mut rank = 0;
+        let mut place = 0;
+        for (point, &count) in home.iter().enumerate() {
+            place += count as usize;
+            rank += binomial(place, point + 1);
+            place += 1;
+        }
+        rank
+    }
+
+    pub fn size(max_checkers: u8) -> usize {
+        binomial(max_checkers as usize + 6, 6)
+    }
+
+    pub fn generate(max_checkers: u8) -> Self {
+        let mut homes = Vec::with_capacity(Self::size(max_checkers));
+        let mut home = [0; 6];
+        Self::collect_homes(&mut home, 0, max_checkers, &mut homes);
+        // every play lowers the pip count, so successors are always done first
+        homes.sort_by_key(pips);
+
+        let mut database = Self {
+            max_checkers,
+            distributions: vec![[0.0; MAX_ROLLS]; Self::size(max_checkers)],
+            two_sided_checkers: 0,
+            two_sided: Vec::new(),
+        };
+        let mut means = vec![0.0_f64; database.distributions.len()];
+        let rolls: Vec<(Vec<u8>, f64)> = all_rolls()
+            .into_iter()
+            .map(|(dice_1, dice_2, chance)| (dice_from_roll(dice_1, dice_2), chance))
+            .collect();
+        for home in homes {
+            let index = database.index(&home);
+            let mut distribution = [0.0; MAX_ROLLS];
+            if pips(&home) == 0 {
+                distribution[0] = 1.0;
+            } else {
+                for (dice, chance) in &rolls {
+                    let best = successors(&home, dice)
+                        .into_iter()
+                        .map(|next| database.index(&next))
+                        .min_by(|&a, &b| means[a].total_cmp(&means[b]))
+                        .unwrap();
+                    // one roll more than from the best successor
+                    let after = &database.distributions[best];
+                    for (total, rest) in distribution[1..].iter_mut().zip(after) {
+                        *total += chance * rest;
+                    }
+                }
+            }
+            means[index] = distribution
+                .iter()
+                .enumerate()
+                .map(|(rolls, chance)| rolls as f64 * chance)
+                .sum();
+            database.distributions[index] = distribution;
+        }
+        database.generate_two_sided(max_checkers.min(TWO_SIDED_CHECKERS), &rolls);
+        database
+    }
+
+    fn generate_two_sided(&mut self, checkers: u8, rolls: &[(Vec<u8>, f64)]) {
+        let size = Self::size(checkers);
+        let mut homes = Vec::with_capacity(size);
+        Self::collect_homes(&mut [0; 6], 0, checkers, &mut homes);
+        let mut successors_by_roll = vec![Vec::new(); size];
+        let mut pips_by_index = vec![0; size];
+        for home in &homes {
+            let index = self.index(home);
+            pips_by_index[index] = pips(home);
+            successors_by_roll[index] = rolls
+                .iter()
+                .map(|(dice, _)| {
+                    successors(home, dice)
+                        .iter()
+                        .map(|next| self.index(next))
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+        }
+
+        // a play lowers the mover's pips, so every pair is done after the pairs it leads to
+        let mut pairs: Vec<(usize, usize)> = (0..size)
+            .flat_map(|own| (0..size).map(move |other| (own, other)))
+            .collect();
+        pairs.sort_by_key(|&(own, other)| pips_by_index[own] + pips_by_index[other]);
+
+        let mut chances = vec![0.0; size * size];
+        for (own, other) in pairs {
+            // index 0 is the empty home, the side with nothing left has already won
+            chances[own * size + other] = if own == 0 {
+                1.0
+            } else if other == 0 {
+                0.0
+            } else {
+                rolls
+                    .iter()
+                    .zip(&successors_by_roll[own])
+                    .map(|((_, chance), nexts)| {
+                        let best = nexts
+                            .iter()
+                            .map(|&next| 1.0 - chances[other * size + next])
+                            .fold(0.0, f64::max);
+                        chance * best
+                    })
+                    .sum::<f64>()
+                    .min(1.0)
+            };
+        }
+        self.two_sided_checkers = checkers;
+        self.two_sided = chances;
+    }
+
+    fn collect_homes(home: &mut Home, point: usize, left: u8, homes: &mut Vec<Home>) {
+        if point == 6 {
+            homes.push(*home);
+            return;
+        }
+        for count in 0..=left {
+            home[point] = count;
+            Self::collect_homes(home, point + 1, left - count, homes);
+        }
+        home[point] = 0;
+    }
+
+    // checkers of `color` by distance from the tray, None when they're not all home yet
+    pub fn home(&self, position: &Position, color: u8) -> Option<Home> {
+        if position.bar[color as usize] > 0 || !position.are_all_home(color) {
+            return None;
+        }
+        let mut home = [0; 6];
+        for (point, count) in home.iter_mut().enumerate() {
+            let field = if color == WHITE {
+                point + 1
+            } else {
+                24 - point
+            };
+            if position.which_color(field) == Some(color) {
+                *count = position.checker_count(field);
+            }
+        }
+        let total: u8 = home.iter().sum();
+        (total <= self.max_checkers).then_some(home)
+    }
+
+    pub fn distribution(&self, home: &Home) -> &[f64] {
+        &self.distributions[self.index(home)]
+    }
+
+    pub fn expected_rolls(&self, home: &Home) -> f64 {
+        self.distribution(home)
+            .iter()
+            .enumerate()
+            .map(|(rolls, chance)| rolls as f64 * chance)
+            .sum()
+    }
+
+    // expected rolls to bear off in pips
+    pub fn effective_pip_count(&self, position: &Position, color: u8) -> Option<f64> {
+        Some(self.expected_rolls(&self.home(position, color)?) * AVERAGE_ROLL)
+    }
+
+    // exact chance that `on_roll` wins, None unless both sides fit in the two-sided part
+    pub fn exact_win_probability(&self, position: &Position, on_roll: u8) -> Option<f64> {
+        if !position.is_race() {
+            return None;
+        }
+        let covered = |home: Home| {
+            (home.iter().sum::<u8>() <= self.two_sided_checkers).then(|| self.index(&home))
+        };
+        let own = covered(self.home(position, on_roll)?)?;
+        let other = covered(self.home(position, opponent(on_roll))?)?;
+        Some(self.two_sided[own * Self::size(self.two_sided_checkers) + other])
+    }
+
+    // chance that `on_roll` bears off first, both sides have to be covered and out of contact;
+    // exact from the two-sided part where it covers both sides
+    pub fn win_probability(&self, position: &Position, on_roll: u8) -> Option<f64> {
+        if !position.is_race() {
+            return None;
+        }
+        if let Some(chance) = self.exact_win_probability(position, on_roll) {
+            return Some(chance);
+        }
+        let own = self.distribution(&self.home(position, on_roll)?);
+        let other = self.distribution(&self.home(position, opponent(on_roll))?);
+        // the side on roll wins when it needs no more rolls than the other side
+        let mut other_needs_at_least = 1.0;
+        let mut chance = 0.0;
+        for rolls in 0..MAX_ROLLS {
+            chance += own[rolls] * other_needs_at_least;
+            other_needs_at_least -= other[rolls];
+        }
+        Some(chance.clamp(0.0, 1.0))
+    }
+
+    // best bear-off play once there's no contact: highest winning chance against a covered
+    // opponent, otherwise the fewest expected rolls
+    pub fn best_play(&self, position: &Position, dice: &[u8]) -> Option<Play> {
+        if !position.is_race() {
+            return None;
+        }
+        let color = position.turn;
+        self.home(position, color)?;
+        let against = self.home(position, opponent(color)).is_some();
+        let score = |play: &Play| {
+            let mut next = *position;
+            next.apply_play(play, dice);
+            if next.tray[color as usize] == 15 {
+                return f64::MAX;
+            }
+            let home = self.home(&next, color).unwrap_or_default();
+            if against {
+                next.turn = opponent(color);
+                1.0 - self.win_probability(&next, next.turn).unwrap_or(0.0)
+            } else {
+                -self.expected_rolls(&home)
+            }
+        };
+        position
+            .generate_plays(dice)
+            .into_iter()
+            .map(|play| (score(&play), play))
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, play)| play)
+    }
+
+    // "BOFF", max checkers, then per position the first roll count with a chance, how many
+    // follow and the chances as 16-bit fractions; then "BTWO", the two-sided checkers and
+    // the winning chance of every pair as a 16-bit fraction
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(self.max_checkers);
+        for distribution in &self.distributions {
+            let scaled = distribution.map(|chance| (chance * 65535.0).round() as u16);
+            let first = scaled.iter().position(|&x| x > 0).unwrap_or(0);
+            let last = scaled.iter().rposition(|&x| x > 0).unwrap_or(0);
+            bytes.push(first as u8);
+            bytes.push((last + 1 - first) as u8);
+            for value in &scaled[first..=last] {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes.extend(TWO_SIDED_MAGIC);
+        bytes.push(self.two_sided_checkers);
+        for chance in &self.two_sided {
+            bytes.extend(((chance * 65535.0).round() as u16).to_le_bytes());
+        }
+        fs::write(path, bytes)
+    }
+
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let bytes = read(path)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "Invalid bear-off database");
+        if bytes.len() < 5 || &bytes[..4] != MAGIC || bytes[4] > MAX_CHECKERS {
+            return Err(invalid());
+        }
+        let max_checkers = bytes[4];
+        let mut distributions = Vec::with_capacity(Self::size(max_checkers));
+        let mut rest = &bytes[5..];
+        for _ in 0..Self::size(max_checkers) {
+            let [first, length, ..] = *rest else {
+                return Err(invalid());
+            };
+            let (first, length) = (first as usize, length as usize);
+            if first + length > MAX_ROLLS || rest.len() < 2 + 2 * length {
+                return Err(invalid());
+            }
+            let mut distribution = [0.0; MAX_ROLLS];
+            for (i, value) in rest[2..2 + 2 * length].chunks(2).enumerate() {
+                distribution[first + i] = u16::from_le_bytes([value[0], value[1]]) as f64 / 65535.0;
+            }
+            distributions.push(distribution);
+            rest = &rest[2 + 2 * length..];
+        }
+
+        // databases from older versions end here and have no two-sided part
+        let mut database = Self {
+            max_checkers,
+            distributions,
+            two_sided_checkers: 0,
+            two_sided: Vec::new(),
+        };
+        if rest.is_empty() {
+            return Ok(database);
+        }
+        if rest.len() < 5 || &rest[..4] != TWO_SIDED_MAGIC || rest[4] > max_checkers {
+            return Err(invalid());
+        }
+        let size = Self::size(rest[4]);
+        if rest.len() != 5 + 2 * size * size {
+            return Err(invalid());
+        }
+        database.two_sided_checkers = rest[4];
+        database.two_sided = rest[5..]
+            .chunks(2)
+            .map(|value| u16::from_le_bytes([value[0], value[1]]) as f64 / 65535.0)
+            .collect();
+        Ok(database)
+    }
+}
+
+// race equity from the database where it covers both sides and gammons are no longer possible,
+// exact where the two-sided part covers them, `fallback` everywhere else
+pub struct WithBearoff<'a> {
+    pub database: &'a BearoffDatabase,
+    pub fallback: &'a dyn Evaluator,
+}
+
+impl Evaluator for WithBearoff<'_> {
+    fn equity(&self, position: &Position, color: u8) -> f64 {
+        let other = opponent(color);
+        if position.tray[color as usize] > 0
+            && position.tray[other as usize] > 0
+            && let Some(chance) = self.database.win_probability(position, other)
+        {
+            return 1.0 - 2.0 * chance;
+        }
+        self.fallback.equity(position, color)
+    }
+
+    fn probabilities(&self, position: &Position, color: u8) -> Probabilities {
+        let other = opponent(color);
+        if position.tray[color as usize] > 0
+            && position.tray[other as usize] > 0
+            && let Some(chance) = self.database.win_probability(position, other)
+        {
+            return Probabilities {
+                win: 1.0 - chance,
+                ..Probabilities::default()
+            };
+        }
+        self.fallback.probabilities(position, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::BLACK;
+    use crate::search::Heuristic;
+
+    #[test]
+    fn index_is_a_bijection() {
+        let database = BearoffDatabase::generate(4);
+        let mut homes = Vec::new();
+        BearoffDatabase::collect_homes(&mut [0; 6], 0, 4, &mut homes);
+        let mut indexes: Vec<usize> = homes.iter().map(|home| database.index(home)).collect();
+        indexes.sort();
+        assert_eq!(indexes, (0..BearoffDatabase::size(4)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn one_checker_distributions() {
+        let database = BearoffDatabase::generate(2);
+        assert_eq!(database.expected_rolls(&[0; 6]), 0.0);
+        assert!((database.distribution(&[1, 0, 0, 0, 0, 0])[1] - 1.0).abs() < 1e-12);
+        // everything but 1-1, 1-2, 1-3, 2-1, 3-1 and 1-4, 4-1, 2-3, 3-2 takes it off at once
+        let six_point = database.distribution(&[0, 0, 0, 0, 0, 1]);
+        assert!((six_point[1] - 27.0 / 36.0).abs() < 1e-12);
+        assert!((six_point[2] - 9.0 / 36.0).abs() < 1e-12);
+
+        let position = Position::from_checkers(&[(1, WHITE, 1)], WHITE, [0, 0], [14, 15]);
+        let pips = database.effective_pip_count(&position, WHITE).unwrap();
+        assert!((pips - 49.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn successors_match_rules_engine() {
+        let homes = [[2, 0, 1, 0, 3, 1], [0, 0, 0, 0, 0, 4], [1, 1, 0, 0, 0, 0]];
+        for home in homes {
+            let checkers: Vec<(usize, u8, u8)> = home
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(point, &count)| (point + 1, WHITE, count))
+                .collect();
+            let off = 15 - home.iter().sum::<u8>();
+            let mut position = Position::from_checkers(&checkers, WHITE, [0, 0], [off, 0]);
+            position.board[23] = crate::position::Point::new(BLACK, 15);
+            for (dice_1, dice_2, _) in all_rolls() {
+                let dice = dice_from_roll(dice_1, dice_2);
+                let mut expected: Vec<Home> = position
+                    .generate_plays(&dice)
+                    .iter()
+                    .map(|play| {
+                        let mut next = position;
+                        next.apply_play(play, &dice);
+                        let mut home = [0; 6];
+                        for (point, count) in home.iter_mut().enumerate() {
+                            if next.which_color(point + 1) == Some(WHITE) {
+                                *count = next.checker_count(point + 1);
+                            }
+                        }
+                        home
+                    })
+                    .collect();
+                expected.sort();
+                expected.dedup();
+                assert_eq!(successors(&home, &dice), expected, "{home:?} {dice:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn race_win_probability() {
+        let database = BearoffDatabase::generate(3);
+        let position =
+            Position::from_checkers(&[(1, WHITE, 1), (24, BLACK, 1)], WHITE, [0, 0], [14, 14]);
+        assert_eq!(database.win_probability(&position, WHITE), Some(1.0));
+        let evaluator = WithBearoff {
+            database: &database,
+            fallback: &Heuristic,
+        };
+        // black just moved and white is on roll
+        assert_eq!(evaluator.equity(&position, BLACK), -1.0);
+        assert_eq!(database.win_probability(&Position::new(), WHITE), None);
+    }
+
+    #[test]
+    fn two_sided_chances_are_exact() {
+        let database = BearoffDatabase::generate(3);
+        // one checker each on the 6 point: off at once unless 9 of 36 rolls miss, and after
+        // a miss the other side has to miss too
+        let position =
+            Position::from_checkers(&[(6, WHITE, 1), (19, BLACK, 1)], WHITE, [0, 0], [14, 14]);
+        let exact = database.exact_win_probability(&position, WHITE).unwrap();
+        assert!((exact - (27.0 / 36.0 + 9.0 / 36.0 * 9.0 / 36.0)).abs() < 1e-12);
+
+        // the chance on roll is the best play's chance for every roll, through the rules engine
+        let position = Position::from_checkers(
+            &[(5, WHITE, 1), (2, WHITE, 2), (20, BLACK, 2), (23, BLACK, 1)],
+            WHITE,
+            [0, 0],
+            [12, 12],
+        );
+        let expected: f64 = all_rolls()
+            .into_iter()
+            .map(|(dice_1, dice_2, chance)| {
+                let dice = dice_from_roll(dice_1, dice_2);
+                let best = position
+                    .generate_plays(&dice)
+                    .iter()
+                    .map(|play| {
+                        let mut next = position;
+                        next.apply_play(play, &dice);
+                        if next.tray[WHITE as usize] == 15 {
+                            return 1.0;
+                        }
+                        1.0 - database.exact_win_probability(&next, BLACK).unwrap()
+                    })
+                    .fold(0.0, f64::max);
+                chance * best
+            })
+            .sum();
+        let exact = database.win_probability(&position, WHITE).unwrap();
+        assert!((exact - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn two_sided_covers_only_small_homes() {
+        let database = BearoffDatabase::generate(8);
+        assert_eq!(database.two_sided_checkers, TWO_SIDED_CHECKERS);
+        let position =
+            Position::from_checkers(&[(1, WHITE, 7), (24, BLACK, 1)], WHITE, [0, 0], [8, 14]);
+        assert_eq!(database.exact_win_probability(&position, WHITE), None);
+        assert!(database.win_probability(&position, WHITE).is_some());
+    }
+
+    #[test]
+    fn best_play_bears_off_when_it_can() {
+        let database = BearoffDatabase::generate(3);
+        let position = Position::from_checkers(
+            &[(6, WHITE, 1), (2, WHITE, 1), (24, BLACK, 2)],
+            WHITE,
+            [0, 0],
+            [13, 13],
+        );
+        let play = database
+            .best_play(&position, &dice_from_roll(6, 2))
+            .unwrap();
+        let mut next = position;
+        next.apply_play(&play, &dice_from_roll(6, 2));
+        assert_eq!(next.tray[WHITE as usize], 15);
+    }
+
+    #[test]
+    fn best_play_leaves_contact_to_the_search() {
+        let database = BearoffDatabase::generate(3);
+        // black's checker on white's 3 point still has to pass white's checkers
+        let position = Position::from_checkers(
+            &[(6, WHITE, 1), (4, WHITE, 1), (3, BLACK, 1), (24, BLACK, 1)],
+            WHITE,
+            [0, 0],
+            [13, 13],
+        );
+        assert_eq!(database.best_play(&position, &dice_from_roll(2, 1)), None);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let database = BearoffDatabase::generate(3);
+        let path = std::env::temp_dir().join("cli_backgammon_bearoff_test.db");
+        let path = path.to_str().unwrap();
+        database.save(path).unwrap();
+        let loaded = BearoffDatabase::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.max_checkers, 3);
+        for (a, b) in loaded.distributions.iter().zip(&database.distributions) {
+            assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4));
+        }
+        assert_eq!(loaded.two_sided_checkers, 3);
+        assert_eq!(loaded.two_sided.len(), database.two_sided.len());
+        let two_sided = loaded.two_sided.iter().zip(&database.two_sided);
+        assert!(two_sided.into_iter().all(|(x, y)| (x - y).abs() < 1e-4));
+    }
+}
@@ -0,0 +1,26 @@
+// generates the bear-off database used by the searching computer players
+// usage: bearoff [max checkers] [database file]
+use cli_backgammon::bearoff::{self, BearoffDatabase, MAX_CHECKERS};
+use std::{env, process, time::Instant};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let max_checkers = args
+        .first()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(MAX_CHECKERS)
+        .min(MAX_CHECKERS);
+    let path = args.get(1).map_or(bearoff::DEFAULT_PATH, String::as_str);
+
+    let start = Instant::now();
+    println!(
+        "Generating {} positions with up to {max_checkers} checkers",
+        BearoffDatabase::size(max_checkers)
+    );
+    let database = BearoffDatabase::generate(max_checkers);
+    if let Err(error) = database.save(path) {
+        eprintln!("Couldn't save {path}: {error}");
+        process::exit(1);
+    }
+    println!("Saved {path} in {:.1?}", start.elapsed());
+}
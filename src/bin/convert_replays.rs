@@ -0,0 +1,56 @@
+// rewrites snapshot replays from older versions as move lists, converted ones are left alone
+// usage: convert_replays [replay files], every file in saves/replays but .mat and .sgf files by default
+use cli_backgammon::replay::Replay;
+use std::{env, fs, process};
+
+const REPLAY_DIR: &str = "saves/replays";
+
+fn convert(path: &str) -> Result<String, String> {
+    let content = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    if Replay::parse(&content).is_some() {
+        return Ok("already a move list".to_string());
+    }
+    let replay = Replay::convert(&content).ok_or("not a replay")?.to_string();
+    fs::write(path, &replay).map_err(|error| error.to_string())?;
+    Ok(format!(
+        "{} lines instead of {}",
+        replay.lines().count(),
+        content.lines().count()
+    ))
+}
+
+fn main() {
+    let mut paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        let Ok(entries) = fs::read_dir(REPLAY_DIR) else {
+            eprintln!("Couldn't read {REPLAY_DIR}");
+            process::exit(1);
+        };
+        paths = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_none_or(|ext| ext != "mat" && ext != "sgf")
+            })
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        paths.sort();
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        match convert(path) {
+            Ok(message) => println!("{path}: {message}"),
+            Err(error) => {
+                eprintln!("{path}: {error}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
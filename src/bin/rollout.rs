@@ -0,0 +1,143 @@
+// Monte Carlo rollouts of a saved position, or of the best plays for a given roll
+// usage: rollout <save file> [--games N] [--seed S] [--threads T] [--policy greedy|expert|neural]
+//                            [--dice 31] [--plays N]
+use cli_backgammon::{
+    bearoff::{self, BearoffDatabase, WithBearoff},
+    bot::Difficulty,
+    network::{self, Network},
+    notation,
+    position::{Position, WHITE},
+    rollout::{self, RolloutConfig, RolloutResult},
+    rules::{Play, dice_from_roll},
+    search::{self, Evaluator, Heuristic, SearchConfig},
+};
+use std::{env, fs::read_to_string, process, sync::Arc};
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
+
+fn format_result(result: &RolloutResult) -> String {
+    let percent = |estimate: rollout::Estimate| {
+        format!(
+            "{:.2}% ± {:.2}",
+            estimate.mean * 100.0,
+            estimate.interval * 100.0
+        )
+    };
+    format!(
+        "win {} (gammon {}, backgammon {}), lose gammon {} (backgammon {}), equity {:+.3} ± {:.3}",
+        percent(result.win),
+        percent(result.win_gammon),
+        percent(result.win_backgammon),
+        percent(result.lose_gammon),
+        percent(result.lose_backgammon),
+        result.equity.mean,
+        result.equity.interval
+    )
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(path) = args.first() else {
+        fail(
+            "usage: rollout <save file> [--games N] [--seed S] [--threads T] [--policy greedy|expert|neural] [--dice 31] [--plays N]",
+        );
+    };
+    let content = read_to_string(path).unwrap_or_else(|error| fail(&format!("{path}: {error}")));
+    let Some(position) = Position::parse_save(&content) else {
+        fail(&format!("{path} is not a valid save file"));
+    };
+
+    let mut config = RolloutConfig::default();
+    let mut dice = None;
+    let mut candidates = 5;
+    for pair in args[1..].chunks(2) {
+        let [flag, value] = pair else {
+            fail(&format!("Missing value for {}", pair[0]));
+        };
+        let number = || -> u64 {
+            value
+                .parse()
+                .unwrap_or_else(|_| fail(&format!("Invalid value for {flag}: {value}")))
+        };
+        match flag.as_str() {
+            "--games" => {
+                config.games = u32::try_from(number())
+                    .ok()
+                    .filter(|&games| games > 0)
+                    .unwrap_or_else(|| fail(&format!("Invalid value for {flag}: {value}")))
+            }
+            "--seed" => config.seed = number(),
+            "--threads" => config.threads = number() as usize,
+            "--plays" => candidates = number() as usize,
+            "--dice" => match value.as_bytes() {
+                [dice_1 @ b'1'..=b'6', dice_2 @ b'1'..=b'6'] => {
+                    dice = Some(dice_from_roll(dice_1 - b'0', dice_2 - b'0'))
+                }
+                _ => fail(&format!("Invalid dice: {value}")),
+            },
+            "--policy" => {
+                config.policy = match value.as_str() {
+                    "greedy" => Difficulty::Greedy,
+                    "expert" => Difficulty::Expert(1),
+                    "neural" => Difficulty::Neural {
+                        network: Arc::new(Network::load(network::DEFAULT_PATH).unwrap_or_else(
+                            |_| fail(&format!("No network in {}", network::DEFAULT_PATH)),
+                        )),
+                        depth: 1,
+                    },
+                    _ => fail(&format!("Unknown policy: {value}")),
+                }
+            }
+            _ => fail(&format!("Unknown option: {flag}")),
+        }
+    }
+    let bearoff = BearoffDatabase::load(bearoff::DEFAULT_PATH).ok();
+    let side = if position.turn == WHITE {
+        "White"
+    } else {
+        "Black"
+    };
+
+    let Some(dice) = dice else {
+        let result = rollout::rollout(&position, &config, bearoff.as_ref());
+        println!("{side} on roll, {} games:", result.games);
+        println!("{}", format_result(&result));
+        return;
+    };
+
+    // the most promising plays by the static evaluation are rolled out
+    let fallback: &dyn Evaluator = match &config.policy {
+        Difficulty::Neural { network, .. } => network.as_ref(),
+        _ => &Heuristic,
+    };
+    let config_1ply = SearchConfig {
+        depth: 1,
+        ..SearchConfig::default()
+    };
+    let ranked = match &bearoff {
+        Some(database) => {
+            let evaluator = WithBearoff { database, fallback };
+            search::rank_plays(&position, &dice, &config_1ply, &evaluator)
+        }
+        None => search::rank_plays(&position, &dice, &config_1ply, fallback),
+    };
+    let plays: Vec<Play> = ranked
+        .into_iter()
+        .take(candidates)
+        .map(|ranked| ranked.play)
+        .collect();
+    let results = rollout::rollout_plays(&position, &dice, &plays, &config, bearoff.as_ref());
+    let mut rows: Vec<(&Play, RolloutResult)> = plays.iter().zip(results).collect();
+    rows.sort_by(|a, b| b.1.equity.mean.total_cmp(&a.1.equity.mean));
+    println!("{side} to play {dice:?}, {} games per play:", config.games);
+    for (play, result) in rows {
+        println!(
+            "{}: {}",
+            notation::format_play(&position, play),
+            format_result(&result)
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// TD(lambda) self-play training for the neural computer player
+// usage: train [games] [weights file] [hidden units]
+use cli_backgammon::{
+    bot::{self, Difficulty},
+    network::{self, Network},
+    position::{BLACK, Position, WHITE},
+    rules::dice_from_roll,
+};
+use rand::Rng;
+use std::{env, process, sync::Arc};
+
+const ALPHA: f64 = 0.1;
+const LAMBDA: f64 = 0.7;
+const REPORT_EVERY: u32 = 1000;
+const TEST_GAMES: u32 = 200;
+
+// returns the winner
+fn play_game(players: [&Difficulty; 2], rng: &mut impl Rng) -> u8 {
+    let mut position = Position::new();
+    position.turn = if rng.random() { WHITE } else { BLACK };
+    loop {
+        let dice = dice_from_roll(rng.random_range(1..=6), rng.random_range(1..=6));
+        let play = bot::choose_play(&position, &dice, players[position.turn as usize], None);
+        position.apply_play(&play, &dice);
+        if position.is_over() {
+            return position.turn;
+        }
+        position.change_turn();
+    }
+}
+
+// share of games the network wins against the greedy bot, playing both colors
+fn test_against_greedy(network: &Network, rng: &mut impl Rng) -> f64 {
+    let neural = Difficulty::Neural {
+        network: Arc::new(network.clone()),
+        depth: 1,
+    };
+    let greedy = Difficulty::Greedy;
+    let mut wins = 0;
+    for game in 0..TEST_GAMES {
+        let color = if game % 2 == 0 { WHITE } else { BLACK };
+        let players = if color == WHITE {
+            [&neural, &greedy]
+        } else {
+            [&greedy, &neural]
+        };
+        if play_game(players, rng) == color {
+            wins += 1;
+        }
+    }
+    wins as f64 / TEST_GAMES as f64
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let games: u32 = args.first().and_then(|x| x.parse().ok()).unwrap_or(10000);
+    let path = args.get(1).map_or(network::DEFAULT_PATH, String::as_str);
+    let hidden = args
+        .get(2)
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(network::DEFAULT_HIDDEN);
+
+    let mut rng = rand::rng();
+    // an existing network keeps training
+    let mut network = match Network::load(path) {
+        Ok(network) => {
+            println!("Continuing training of {path}");
+            network
+        }
+        Err(_) => Network::new(hidden, rng.random()),
+    };
+
+    let mut plays = 0;
+    let mut played_games = 0;
+    for game in 1..=games {
+        plays += network.train_game(&mut rng, ALPHA, LAMBDA);
+        played_games += 1;
+        if game % REPORT_EVERY == 0 || game == games {
+            let won = test_against_greedy(&network, &mut rng);
+            println!(
+                "{game} games, {:.1} plays per game, {:.1}% won against greedy",
+                plays as f64 / played_games as f64,
+                won * 100.0
+            );
+            plays = 0;
+            played_games = 0;
+            if let Err(error) = network.save(path) {
+                eprintln!("Couldn't save {path}: {error}");
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::bearoff::{BearoffDatabase, WithBearoff};
+use crate::cube::{self, CubeAnalysis, CubeState};
+use crate::network::Network;
+use crate::position::{Position, WHITE, opponent};
+use crate::rules::{Move, Play, dice_from_roll};
+use crate::search::{self, Evaluator, Heuristic, SearchConfig};
+use rand::seq::IndexedRandom;
+use std::sync::Arc;
+
+const WIN_VALUE: f64 = 1000.0;
+const LOOKAHEAD_WIDTH: usize = 8; // best greedy candidates searched one roll deeper
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difficulty {
+    Random,      // any legal play
+    Greedy,      // best play by the static evaluation
+    Lookahead,   // best play against every reply of the opponent
+    Expert(u32), // expectiminimax search to this many plies
+    Neural {
+        network: Arc<Network>, // trained by self-play, see src/bin/train.rs
+        depth: u32,
+    },
+}
+
+impl Difficulty {
+    pub fn name(&self) -> String {
+        match self {
+            Difficulty::Random => "random".to_string(),
+            Difficulty::Greedy => "greedy".to_string(),
+            Difficulty::Lookahead => "lookahead".to_string(),
+            Difficulty::Expert(depth) => format!("expert, {depth}-ply"),
+            Difficulty::Neural { depth, .. } => format!("neural, {depth}-ply"),
+        }
+    }
+}
+
+// the 21 distinct rolls with their probability out of 36
+pub fn all_rolls() -> Vec<(u8, u8, f64)> {
+    let mut rolls = Vec::new();
+    for dice_1 in 1..=6 {
+        for dice_2 in dice_1..=6 {
+            let chance = if dice_1 == dice_2 { 1.0 } else { 2.0 };
+            rolls.push((dice_1, dice_2, chance / 36.0));
+        }
+    }
+    rolls
+}
+
+// play for the side on turn, empty when nothing can be moved. The searching levels play
+// bear-offs perfectly when a bear-off database is given
+pub fn choose_play(
+    position: &Position,
+    dice: &[u8],
+    difficulty: &Difficulty,
+    bearoff: Option<&BearoffDatabase>,
+) -> Play {
+    let plays = position.generate_plays(dice);
+    let color = position.turn;
+    let best = match difficulty {
+        Difficulty::Random => plays.choose(&mut rand::rng()).cloned(),
+        Difficulty::Greedy => plays
+            .iter()
+            .max_by(|a, b| {
+                let a = evaluate(&after(position, a, dice), color);
+                let b = evaluate(&after(position, b, dice), color);
+                a.total_cmp(&b)
+            })
+            .cloned(),
+        Difficulty::Lookahead => {
+            let mut candidates: Vec<(f64, &Play)> = plays
+                .iter()
+                .map(|play| (evaluate(&after(position, play, dice), color), play))
+                .collect();
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+            candidates.truncate(LOOKAHEAD_WIDTH);
+            candidates
+                .into_iter()
+                .map(|(_, play)| (lookahead(&after(position, play, dice), color), play))
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, play)| play.clone())
+        }
+        Difficulty::Expert(depth) => search_play(position, dice, *depth, &Heuristic, bearoff),
+        Difficulty::Neural { network, depth } => {
+            search_play(position, dice, *depth, network.as_ref(), bearoff)
+        }
+    };
+    best.unwrap_or_default()
+}
+
+// cube actions for the side on turn, None for the random level, which never doubles and
+// always takes
+pub fn cube_analysis(
+    position: &Position,
+    cube: &CubeState,
+    difficulty: &Difficulty,
+    bearoff: Option<&BearoffDatabase>,
+) -> Option<CubeAnalysis> {
+    let evaluator: &dyn Evaluator = match difficulty {
+        Difficulty::Random => return None,
+        Difficulty::Neural { network, .. } => network.as_ref(),
+        _ => &Heuristic,
+    };
+    let analysis = match bearoff {
+        Some(database) => {
+            let evaluator = WithBearoff {
+                database,
+                fallback: evaluator,
+            };
+            cube::analyze_position(position, cube, &evaluator)
+        }
+        None => cube::analyze_position(position, cube, evaluator),
+    };
+    Some(analysis)
+}
+
+fn search_play(
+    position: &Position,
+    dice: &[u8],
+    depth: u32,
+    evaluator: &dyn Evaluator,
+    bearoff: Option<&BearoffDatabase>,
+) -> Option<Play> {
+    let config = SearchConfig {
+        depth,
+        ..SearchConfig::default()
+    };
+    if let Some(database) = bearoff {
+        if let Some(play) = database.best_play(position, dice) {
+            return Some(play);
+        }
+        let evaluator = WithBearoff {
+            database,
+            fallback: evaluator,
+        };
+        return search::rank_plays(position, dice, &config, &evaluator)
+            .into_iter()
+            .next()
+            .map(|ranked| ranked.play);
+    }
+    search::rank_plays(position, dice, &config, evaluator)
+        .into_iter()
+        .next()
+        .map(|ranked| ranked.play)
+}
+
+fn after(position: &Position, play: &[Move], dice: &[u8]) -> Position {
+    let mut next = *position;
+    next.apply_play(play, dice);
+    next
+}
+
+// average over the opponent's rolls of our static evaluation after their reply that's worst
+// for us
+fn lookahead(position: &Position, color: u8) -> f64 {
+    if position.is_over() {
+        return evaluate(position, color);
+    }
+    let mut next = *position;
+    next.turn = opponent(color);
+    all_rolls()
+        .into_iter()
+        .map(|(dice_1, dice_2, chance)| {
+            let dice = dice_from_roll(dice_1, dice_2);
+            let worst = next
+                .generate_plays(&dice)
+                .iter()
+                .map(|play| evaluate(&after(&next, play, &dice), color))
+                .min_by(f64::total_cmp)
+                .unwrap_or(f64::MIN);
+            chance * worst
+        })
+        .sum()
+}
+
+// static evaluation from `color`'s side, higher is better
+pub fn evaluate(position: &Position, color: u8) -> f64 {
+    let other = opponent(color);
+    if position.tray[color as usize] == 15 {
+        return WIN_VALUE;
+    }
+    if position.tray[other as usize] == 15 {
+        return -WIN_VALUE;
+    }
+
+    let race = position.pip_count(other) as f64 - position.pip_count(color) as f64;
+    if position.is_race() {
+        return race;
+    }
+    race + side_score(position, color) - side_score(position, other)
+}
+
+// made points, primes, hit checkers and blots of one side
+fn side_score(position: &Position, color: u8) -> f64 {
+    let other = opponent(color);
+    let mut score = 8.0 * position.bar[other as usize] as f64;
+
+    let mut prime = 0;
+    for step in 1..=24 {
+        // walk the board in the direction `color` moves, home board last
+        let field = if color == WHITE { 25 - step } else { step };
+        let count = position.checker_count(field);
+        if position.which_color(field) != Some(color) {
+            prime = 0;
+            continue;
+        }
+        if count >= 2 {
+            prime += 1;
+            score += if step > 18 { 3.0 } else { 2.0 };
+            score += prime as f64 * 0.5;
+        } else {
+            prime = 0;
+            score -= blot_risk(position, color, field);
+        }
+    }
+    score
+}
+
+// direct shots count fully, indirect ones half, worse the further the blot has come
+fn blot_risk(position: &Position, color: u8, field: usize) -> f64 {
+    let other = opponent(color);
+    let opponent_bar = if other == WHITE { 25 } else { 0 };
+    let mut shots = 0.0;
+    for source in 0..=25 {
+        let occupied = if source == opponent_bar {
+            position.bar[other as usize] > 0
+        } else {
+            (1..=24).contains(&source) && position.which_color(source) == Some(other)
+        };
+        if !occupied {
+            continue;
+        }
+        let distance = if color == WHITE {
+            field as i32 - source as i32
+        } else {
+            source as i32 - field as i32
+        };
+        match distance {
+            1..=6 => shots += 1.0,
+            7..=12 => shots += 0.5,
+            _ => {}
+        }
+    }
+    let travelled = if color == WHITE { 25 - field } else { field };
+    shots * (2.0 + travelled as f64 / 6.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::BLACK;
+
+    #[test]
+    fn rolls_cover_all_outcomes() {
+        let rolls = all_rolls();
+        assert_eq!(rolls.len(), 21);
+        let total: f64 = rolls.iter().map(|(_, _, chance)| chance).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn every_difficulty_plays_legally() {
+        let position = Position::new();
+        let dice = dice_from_roll(3, 1);
+        let plays = position.generate_plays(&dice);
+        for difficulty in [
+            Difficulty::Random,
+            Difficulty::Greedy,
+            Difficulty::Lookahead,
+            Difficulty::Expert(2),
+            Difficulty::Neural {
+                network: Arc::new(Network::new(8, 1)),
+                depth: 1,
+            },
+        ] {
+            assert!(plays.contains(&choose_play(&position, &dice, &difficulty, None)));
+        }
+    }
+
+    #[test]
+    fn greedy_makes_opening_point() {
+        let position = Position::new();
+        let play = choose_play(&position, &dice_from_roll(3, 1), &Difficulty::Greedy, None);
+        let mut sorted = play.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![(6, 5), (8, 5)]);
+    }
+
+    #[test]
+    fn greedy_hits_a_blot() {
+        let position = Position::from_checkers(
+            &[
+                (6, WHITE, 5),
+                (8, WHITE, 10),
+                (4, BLACK, 1),
+                (20, BLACK, 14),
+            ],
+            WHITE,
+            [0, 0],
+            [0, 0],
+        );
+        let play = choose_play(&position, &dice_from_roll(4, 2), &Difficulty::Greedy, None);
+        let mut next = position;
+        next.apply_play(&play, &dice_from_roll(4, 2));
+        assert_eq!(next.bar[BLACK as usize], 1);
+    }
+
+    #[test]
+    fn blocked_side_gets_empty_play() {
+        let position = Position::from_checkers(
+            &[
+                (1, WHITE, 2),
+                (2, WHITE, 2),
+                (3, WHITE, 2),
+                (4, WHITE, 2),
+                (5, WHITE, 2),
+                (6, WHITE, 2),
+                (10, BLACK, 14),
+            ],
+            BLACK,
+            [0, 1],
+            [3, 0],
+        );
+        let play = choose_play(
+            &position,
+            &dice_from_roll(6, 6),
+            &Difficulty::Lookahead,
+            None,
+        );
+        assert!(play.is_empty());
+    }
+
+    #[test]
+    fn only_random_level_skips_the_cube() {
+        let cube = CubeState::money(1, cube::Ownership::Centered);
+        let position = Position::new();
+        assert!(cube_analysis(&position, &cube, &Difficulty::Random, None).is_none());
+        let analysis = cube_analysis(&position, &cube, &Difficulty::Greedy, None).unwrap();
+        assert!(!analysis.should_double());
+    }
+}
@@ -0,0 +1,372 @@
+use crate::bot::all_rolls;
+use crate::position::Position;
+use crate::rules::dice_from_roll;
+use crate::search::{self, Evaluator, Probabilities, SearchConfig, position_probabilities};
+use std::sync::OnceLock;
+
+// Cube decisions from cubeless chances. Money games use Janowski's model: the cubeful equity
+// mixes the dead cube equity with the equity of a fully live cube by the cube efficiency.
+// In matches the chances are turned into match winning chances with a generated match
+// equity table, treating the cube as dead after the double.
+
+pub const CUBE_EFFICIENCY: f64 = 0.68;
+const GAMMON_RATE: f64 = 0.2; // share of wins that are gammons in the match equity table
+const MAX_AWAY: usize = 64;
+pub const MAX_CUBE_VALUE: u32 = 64; // nobody can double beyond this
+
+// the cube starts at 1 and only ever doubles
+pub fn is_valid_cube_value(value: u32) -> bool {
+    value.is_power_of_two() && value <= MAX_CUBE_VALUE
+}
+
+// who owns the cube, seen from the side on roll
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    Centered,
+    Mine,
+    Theirs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CubeState {
+    pub value: u32,
+    pub owner: Ownership,
+    pub away: Option<[u32; 2]>, // points the side on roll and its opponent need in a match
+    pub is_post_crawford: bool,
+}
+
+impl CubeState {
+    pub fn money(value: u32, owner: Ownership) -> Self {
+        Self {
+            value,
+            owner,
+            away: None,
+            is_post_crawford: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubeDecision {
+    NoDouble,
+    DoubleTake,
+    DoublePass,
+    TooGood, // winning a gammon is worth more than cashing
+}
+
+impl CubeDecision {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CubeDecision::NoDouble => "No double",
+            CubeDecision::DoubleTake => "Double, take",
+            CubeDecision::DoublePass => "Double, pass",
+            CubeDecision::TooGood => "Too good to double",
+        }
+    }
+}
+
+// equities for the side on roll, per unit of the current cube in money games and match
+// winning chances mapped to -1..1 in matches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CubeAnalysis {
+    pub chances: Probabilities,
+    pub cubeless: f64,
+    pub no_double: f64,
+    pub double_take: f64,
+    pub double_pass: f64,
+    pub decision: CubeDecision,
+}
+
+impl CubeAnalysis {
+    pub fn should_double(&self) -> bool {
+        matches!(
+            self.decision,
+            CubeDecision::DoubleTake | CubeDecision::DoublePass
+        )
+    }
+
+    // seen from the opponent, taking must not lose more than passing
+    pub fn should_take(&self) -> bool {
+        self.double_take <= self.double_pass
+    }
+}
+
+// cubeless chances of the side on roll, from its best play for each of the 21 rolls
+pub fn chances_on_roll(position: &Position, evaluator: &dyn Evaluator) -> Probabilities {
+    let color = position.turn;
+    let config = SearchConfig {
+        depth: 1,
+        ..SearchConfig::default()
+    };
+    let mut chances = Probabilities::default();
+    for (dice_1, dice_2, chance) in all_rolls() {
+        let dice = dice_from_roll(dice_1, dice_2);
+        let mut next = *position;
+        if let Some(best) = search::rank_plays(position, &dice, &config, evaluator).first() {
+            next.apply_play(&best.play, &dice);
+        }
+        let after = position_probabilities(&next, color, evaluator);
+        chances.win += chance * after.win;
+        chances.win_gammon += chance * after.win_gammon;
+        chances.lose_gammon += chance * after.lose_gammon;
+    }
+    chances
+}
+
+pub fn analyze_position(
+    position: &Position,
+    cube: &CubeState,
+    evaluator: &dyn Evaluator,
+) -> CubeAnalysis {
+    analyze(&chances_on_roll(position, evaluator), cube)
+}
+
+pub fn analyze(chances: &Probabilities, cube: &CubeState) -> CubeAnalysis {
+    let (no_double, double_take, double_pass) = match cube.away {
+        None => {
+            let owner = if cube.owner == Ownership::Theirs {
+                Ownership::Mine // can't double anyway, rate it as if the cube was ours
+            } else {
+                cube.owner
+            };
+            (
+                cubeful_equity(chances, owner),
+                2.0 * cubeful_equity(chances, Ownership::Theirs),
+                1.0,
+            )
+        }
+        Some([away, other_away]) => {
+            let table = MatchTable::get();
+            let equity = |cube_value: u32| {
+                let mwc =
+                    table.after_game(chances, away, other_away, cube_value, cube.is_post_crawford);
+                2.0 * mwc - 1.0
+            };
+            let pass = table.equity(
+                away as i64 - cube.value as i64,
+                other_away as i64,
+                cube.is_post_crawford,
+            );
+            (equity(cube.value), equity(2 * cube.value), 2.0 * pass - 1.0)
+        }
+    };
+    let decision = if double_take >= double_pass {
+        if no_double > double_pass {
+            CubeDecision::TooGood
+        } else {
+            CubeDecision::DoublePass
+        }
+    } else if double_take > no_double {
+        CubeDecision::DoubleTake
+    } else if no_double >= double_pass {
+        CubeDecision::TooGood
+    } else {
+        CubeDecision::NoDouble
+    };
+    CubeAnalysis {
+        chances: *chances,
+        cubeless: chances.equity(),
+        no_double,
+        double_take,
+        double_pass,
+        decision,
+    }
+}
+
+// average points won per win and lost per loss
+fn win_and_loss(chances: &Probabilities) -> (f64, f64) {
+    let win = if chances.win > 0.0 {
+        1.0 + chances.win_gammon / chances.win
+    } else {
+        1.0
+    };
+    let loss = if chances.win < 1.0 {
+        1.0 + chances.lose_gammon / (1.0 - chances.win)
+    } else {
+        1.0
+    };
+    (win, loss)
+}
+
+// money game equity per unit of cube
+pub fn cubeful_equity(chances: &Probabilities, owner: Ownership) -> f64 {
+    let p = chances.win;
+    let (win, loss) = win_and_loss(chances);
+    let dead = p * win - (1.0 - p) * loss;
+    let x = CUBE_EFFICIENCY;
+    // below the take point we'd pass a double, above the cash point the opponent would
+    let take_point = (loss - 0.5) / (win + loss + 0.5 * x);
+    let cash_point = 1.0 - (win - 0.5) / (win + loss + 0.5 * x);
+    let live = match owner {
+        Ownership::Centered if p <= take_point => -1.0,
+        Ownership::Centered if p >= cash_point => 1.0,
+        Ownership::Centered => -1.0 + 2.0 * (p - take_point) / (cash_point - take_point),
+        Ownership::Mine if p >= cash_point => 1.0,
+        Ownership::Mine => -loss + (1.0 + loss) * p / cash_point,
+        Ownership::Theirs if p <= take_point => -1.0,
+        Ownership::Theirs => -1.0 + (win + 1.0) * (p - take_point) / (1.0 - take_point),
+    };
+    (1.0 - x) * dead + x * live
+}
+
+// Match winning chances before a game, built assuming even games with GAMMON_RATE gammons
+// and no cube, except that the trailer doubles at once after the Crawford game.
+struct MatchTable {
+    pre: Vec<Vec<f64>>, // [away][other away], 1-away rows are the Crawford game
+    post: Vec<f64>,     // leader 1-away after the Crawford game, by the trailer's away
+}
+
+impl MatchTable {
+    // built on first use and shared, every match cube decision needs it
+    fn get() -> &'static Self {
+        static TABLE: OnceLock<MatchTable> = OnceLock::new();
+        TABLE.get_or_init(Self::new)
+    }
+
+    fn new() -> Self {
+        let g = GAMMON_RATE;
+        let mut post = vec![0.0; MAX_AWAY + 1];
+        for away in 1..=MAX_AWAY {
+            post[away] = if away == 1 {
+                0.5
+            } else {
+                let at = |a: usize| if a > 0 { post[a] } else { 0.0 };
+                0.5 + 0.5 * ((1.0 - g) * at(away - 2) + g * at(away.saturating_sub(4)))
+            };
+        }
+        let post_at = |a: i64| if a > 0 { post[a as usize] } else { 0.0 };
+
+        let mut pre = vec![vec![0.0; MAX_AWAY + 1]; MAX_AWAY + 1];
+        for away in 1..=MAX_AWAY {
+            for other_away in 1..=MAX_AWAY {
+                let (a, b) = (away as i64, other_away as i64);
+                pre[away][other_away] = match (away, other_away) {
+                    (1, 1) => 0.5,
+                    (1, _) => 0.5 + 0.5 * ((1.0 - g) * post_at(b - 1) + g * post_at(b - 2)),
+                    (_, 1) => 1.0 - pre[1][away],
+                    _ => {
+                        let at = |a: i64, b: i64| match (a <= 0, b <= 0) {
+                            (true, _) => 1.0,
+                            (_, true) => 0.0,
+                            _ => pre[a as usize][b as usize],
+                        };
+                        0.5 * ((1.0 - g) * at(a - 1, b) + g * at(a - 2, b))
+                            + 0.5 * ((1.0 - g) * at(a, b - 1) + g * at(a, b - 2))
+                    }
+                };
+            }
+        }
+        Self { pre, post }
+    }
+
+    fn equity(&self, away: i64, other_away: i64, is_post_crawford: bool) -> f64 {
+        if away <= 0 {
+            return 1.0;
+        }
+        if other_away <= 0 {
+            return 0.0;
+        }
+        let a = (away as usize).min(MAX_AWAY);
+        let b = (other_away as usize).min(MAX_AWAY);
+        match (is_post_crawford, a, b) {
+            (true, 1, _) => self.post[b],
+            (true, _, 1) => 1.0 - self.post[a],
+            _ => self.pre[a][b],
+        }
+    }
+
+    // match winning chances once this game is over with the cube at `cube_value`
+    fn after_game(
+        &self,
+        chances: &Probabilities,
+        away: u32,
+        other_away: u32,
+        cube_value: u32,
+        is_post_crawford: bool,
+    ) -> f64 {
+        let (a, b, c) = (away as i64, other_away as i64, cube_value as i64);
+        let single_win = chances.win - chances.win_gammon;
+        let single_loss = 1.0 - chances.win - chances.lose_gammon;
+        single_win * self.equity(a - c, b, is_post_crawford)
+            + chances.win_gammon * self.equity(a - 2 * c, b, is_post_crawford)
+            + single_loss * self.equity(a, b - c, is_post_crawford)
+            + chances.lose_gammon * self.equity(a, b - 2 * c, is_post_crawford)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{BLACK, WHITE};
+    use crate::search::Heuristic;
+
+    fn chances(win: f64, win_gammon: f64, lose_gammon: f64) -> Probabilities {
+        Probabilities {
+            win,
+            win_gammon,
+            lose_gammon,
+        }
+    }
+
+    #[test]
+    fn money_decisions_follow_winning_chances() {
+        let cube = CubeState::money(1, Ownership::Centered);
+        let decision = |win| analyze(&chances(win, 0.0, 0.0), &cube).decision;
+        assert_eq!(decision(0.5), CubeDecision::NoDouble);
+        assert_eq!(decision(0.75), CubeDecision::DoubleTake);
+        assert_eq!(decision(0.9), CubeDecision::DoublePass);
+        // gammonish enough to play on for the gammon
+        let analysis = analyze(&chances(0.9, 0.6, 0.0), &cube);
+        assert_eq!(analysis.decision, CubeDecision::TooGood);
+        assert!(!analysis.should_take());
+    }
+
+    #[test]
+    fn live_cube_takes_below_a_quarter() {
+        let cube = CubeState::money(1, Ownership::Centered);
+        // the taker has 24% and 16%, the dead cube take point is 25%
+        assert!(analyze(&chances(0.76, 0.0, 0.0), &cube).should_take());
+        assert!(!analyze(&chances(0.84, 0.0, 0.0), &cube).should_take());
+    }
+
+    #[test]
+    fn match_table_is_consistent() {
+        let table = MatchTable::new();
+        assert_eq!(table.equity(3, 3, false), 0.5);
+        assert!((table.equity(4, 7, false) + table.equity(7, 4, false) - 1.0).abs() < 1e-9);
+        // the Crawford game leader is about 70%
+        assert!((table.equity(1, 2, false) - 0.7).abs() < 0.02);
+        assert!(table.equity(2, 5, false) > table.equity(3, 5, false));
+        assert_eq!(table.equity(0, 3, false), 1.0);
+    }
+
+    #[test]
+    fn doubling_out_at_double_match_point() {
+        // at 2-away 2-away a 1 cube double to 2 makes the game decide the match, so the
+        // decision only depends on the winning chances
+        let cube = CubeState {
+            value: 1,
+            owner: Ownership::Centered,
+            away: Some([2, 2]),
+            is_post_crawford: false,
+        };
+        let analysis = analyze(&chances(0.6, 0.0, 0.0), &cube);
+        assert!((analysis.double_take - 0.2).abs() < 1e-9);
+        assert!(analysis.should_take());
+    }
+
+    #[test]
+    fn closed_out_opponent_gets_doubled() {
+        let mut checkers: Vec<(usize, u8, u8)> = (1..=6).map(|field| (field, WHITE, 2)).collect();
+        checkers.push((7, WHITE, 3));
+        checkers.push((19, BLACK, 12));
+        let position = Position::from_checkers(&checkers, WHITE, [0, 2], [0, 1]);
+        let analysis = analyze_position(
+            &position,
+            &CubeState::money(1, Ownership::Centered),
+            &Heuristic,
+        );
+        assert!(analysis.chances.win > 0.7);
+        assert!(analysis.should_double());
+    }
+}
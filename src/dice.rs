@@ -0,0 +1,160 @@
+use crate::fair_dice::FairProof;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    fmt::Debug,
+    fs::read_to_string,
+    io::{Error, ErrorKind},
+};
+
+// far more dice than a long session rolls, saves claiming more are rejected instead of
+// replaying them all
+pub const MAX_RESUMED_DICE: u64 = 10_000_000;
+
+pub trait DiceSource: Debug {
+    // None when the source can't give another die, e.g. a finished script
+    fn roll_die(&mut self) -> Option<u8>;
+
+    // (seed, dice rolled so far) for sources that can be reproduced
+    fn seed(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    // commitments and secrets of provably fair dice
+    fn fair_proof(&self) -> Option<FairProof> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct SeededDice {
+    seed: u64,
+    rolled: u64,
+    rng: StdRng,
+}
+
+impl SeededDice {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rolled: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // fresh seed, still stored so the game can be reproduced
+    pub fn random() -> Self {
+        Self::new(rand::rng().random())
+    }
+
+    // continues a sequence after `rolled` dice, used when loading saves. None above
+    // MAX_RESUMED_DICE
+    pub fn resume(seed: u64, rolled: u64) -> Option<Self> {
+        if rolled > MAX_RESUMED_DICE {
+            return None;
+        }
+        let mut dice = Self::new(seed);
+        for _ in 0..rolled {
+            dice.roll_die();
+        }
+        Some(dice)
+    }
+}
+
+impl DiceSource for SeededDice {
+    fn roll_die(&mut self) -> Option<u8> {
+        self.rolled += 1;
+        Some(self.rng.random_range(1..=6))
+    }
+
+    fn seed(&self) -> Option<(u64, u64)> {
+        Some((self.seed, self.rolled))
+    }
+}
+
+#[derive(Debug)]
+pub struct ScriptedDice {
+    dice: Vec<u8>,
+    next: usize,
+}
+
+impl ScriptedDice {
+    pub fn new(dice: Vec<u8>) -> Self {
+        Self { dice, next: 0 }
+    }
+
+    // dice separated by whitespace or commas, lines starting with # are comments
+    pub fn parse(content: &str) -> std::io::Result<Self> {
+        let mut dice = Vec::new();
+        for line in content.lines().filter(|line| !line.starts_with('#')) {
+            for value in line.split(|c: char| c.is_whitespace() || c == ',') {
+                if value.is_empty() {
+                    continue;
+                }
+                match value.parse::<u8>() {
+                    Ok(die) if (1..=6).contains(&die) => dice.push(die),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Invalid die in script: {value}"),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(Self::new(dice))
+    }
+
+    pub fn from_file(path: &str) -> std::io::Result<Self> {
+        Self::parse(&read_to_string(path)?)
+    }
+}
+
+impl DiceSource for ScriptedDice {
+    fn roll_die(&mut self) -> Option<u8> {
+        let die = self.dice.get(self.next).copied();
+        if die.is_some() {
+            self.next += 1;
+        }
+        die
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_dice_repeat() {
+        let mut first = SeededDice::new(42);
+        let mut second = SeededDice::new(42);
+        let rolls: Vec<_> = (0..50).map(|_| first.roll_die().unwrap()).collect();
+        assert!(rolls.iter().all(|die| (1..=6).contains(die)));
+        assert_eq!(
+            rolls,
+            (0..50)
+                .map(|_| second.roll_die().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(first.seed(), Some((42, 50)));
+    }
+
+    #[test]
+    fn resume_continues_sequence() {
+        let mut full = SeededDice::new(7);
+        for _ in 0..10 {
+            full.roll_die();
+        }
+        let mut resumed = SeededDice::resume(7, 10).unwrap();
+        assert_eq!(resumed.seed(), full.seed());
+        assert_eq!(resumed.roll_die(), full.roll_die());
+        assert!(SeededDice::resume(7, u64::MAX).is_none());
+    }
+
+    #[test]
+    fn scripted_dice() {
+        let mut dice = ScriptedDice::parse("# opening\n3 1\n6,6\n").unwrap();
+        let rolls: Vec<_> = (0..5).map(|_| dice.roll_die()).collect();
+        assert_eq!(rolls, vec![Some(3), Some(1), Some(6), Some(6), None]);
+        assert!(ScriptedDice::parse("3 7").is_err());
+    }
+}
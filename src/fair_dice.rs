@@ -0,0 +1,203 @@
+use crate::dice::DiceSource;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+// Commit-reveal dice: both players pick a secret and publish SHA-256("secret:nonce") before
+// the game, the random nonce keeps a short secret from being guessed from its commitment.
+// Die number n is taken from SHA-256("white:black:n:round"), so nobody can steer the rolls
+// alone, and once the secrets and nonces are revealed anyone can recompute every roll.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FairProof {
+    pub commitments: [String; 2],
+    pub secrets: [String; 2],
+    pub nonces: [String; 2], // empty for games of older versions, which had none
+    pub rolled: u64,         // dice rolled so far
+}
+
+impl FairProof {
+    // secrets match the published commitments and produce exactly these dice
+    pub fn verify(&self, rolls: &[u8]) -> bool {
+        if commitment(&self.secrets[0], &self.nonces[0]) != self.commitments[0]
+            || commitment(&self.secrets[1], &self.nonces[1]) != self.commitments[1]
+        {
+            return false;
+        }
+        rolls
+            .iter()
+            .enumerate()
+            .all(|(index, &die)| fair_die(&self.secrets, index as u64) == die)
+    }
+
+    // as above, and the dice of every turn are the last ones rolled, in order. The rolls
+    // before them decided who starts.
+    pub fn verify_turns(&self, rolls: &[u8], turns: &[(u8, u8)]) -> bool {
+        let played: Vec<u8> = turns
+            .iter()
+            .flat_map(|&(die_1, die_2)| [die_1, die_2])
+            .collect();
+        self.verify(rolls) && rolls.ends_with(&played)
+    }
+}
+
+pub fn commitment(secret: &str, nonce: &str) -> String {
+    if nonce.is_empty() {
+        // older versions committed to the secret alone
+        return to_hex(&Sha256::digest(secret.as_bytes()));
+    }
+    to_hex(&Sha256::digest(format!("{secret}:{nonce}").as_bytes()))
+}
+
+pub fn random_secret() -> String {
+    let bytes: [u8; 16] = rand::rng().random();
+    to_hex(&bytes)
+}
+
+// secrets are stored space separated, so they have to be single printable words
+pub fn is_valid_secret(secret: &str) -> bool {
+    !secret.is_empty() && secret.chars().all(|c| c.is_ascii_graphic())
+}
+
+// a SHA-256 in hex, as saves store the commitments
+pub fn is_commitment(text: &str) -> bool {
+    text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn fair_die(secrets: &[String; 2], index: u64) -> u8 {
+    // bytes of 252 and above would make 1-4 more likely than 5-6, try the next byte instead
+    for round in 0.. {
+        let input = format!("{}:{}:{}:{}", secrets[0], secrets[1], index, round);
+        if let Some(byte) = Sha256::digest(input.as_bytes())
+            .iter()
+            .find(|&&byte| byte < 252)
+        {
+            return byte % 6 + 1;
+        }
+    }
+    unreachable!()
+}
+
+#[derive(Debug)]
+pub struct FairDice {
+    secrets: [String; 2],
+    nonces: [String; 2],
+    rolled: u64,
+}
+
+impl FairDice {
+    // white's secret first, every secret gets a fresh random nonce
+    pub fn new(secrets: [String; 2]) -> Self {
+        Self {
+            secrets,
+            nonces: [random_secret(), random_secret()],
+            rolled: 0,
+        }
+    }
+
+    // continues after `rolled` dice, used when loading saves
+    pub fn resume(secrets: [String; 2], nonces: [String; 2], rolled: u64) -> Self {
+        Self {
+            secrets,
+            nonces,
+            rolled,
+        }
+    }
+
+    pub fn nonces(&self) -> &[String; 2] {
+        &self.nonces
+    }
+}
+
+impl DiceSource for FairDice {
+    fn roll_die(&mut self) -> Option<u8> {
+        let die = fair_die(&self.secrets, self.rolled);
+        self.rolled += 1;
+        Some(die)
+    }
+
+    fn fair_proof(&self) -> Option<FairProof> {
+        Some(FairProof {
+            commitments: [
+                commitment(&self.secrets[0], &self.nonces[0]),
+                commitment(&self.secrets[1], &self.nonces[1]),
+            ],
+            secrets: self.secrets.clone(),
+            nonces: self.nonces.clone(),
+            rolled: self.rolled,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commitment_is_sha256() {
+        assert_eq!(
+            commitment("abc", ""),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // SHA-256("a:bc")
+        assert_eq!(
+            commitment("a", "bc"),
+            "c140f47475217ae947569d11406e72b36f774f7b5694693b21cddd3282e20439"
+        );
+    }
+
+    #[test]
+    fn same_secret_gets_different_commitments() {
+        let secrets = ["dog".to_string(), "cat".to_string()];
+        let first = FairDice::new(secrets.clone()).fair_proof().unwrap();
+        let second = FairDice::new(secrets).fair_proof().unwrap();
+        assert_ne!(first.commitments, second.commitments);
+        assert_ne!(first.commitments[0], commitment("dog", ""));
+        assert!(first.verify(&[]));
+    }
+
+    #[test]
+    fn rolls_verify_against_revealed_secrets() {
+        let mut dice = FairDice::new(["white".to_string(), "black".to_string()]);
+        let rolls: Vec<u8> = (0..100).map(|_| dice.roll_die().unwrap()).collect();
+        assert!(rolls.iter().all(|die| (1..=6).contains(die)));
+
+        let proof = dice.fair_proof().unwrap();
+        assert!(proof.verify(&rolls));
+
+        let mut tampered = rolls.clone();
+        tampered[40] = tampered[40] % 6 + 1;
+        assert!(!proof.verify(&tampered));
+
+        // the turns are the last pairs of dice, after the opening roll
+        let turns: Vec<(u8, u8)> = rolls[2..]
+            .chunks(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect();
+        assert!(proof.verify_turns(&rolls, &turns));
+        let mut edited = turns.clone();
+        edited[10].0 = edited[10].0 % 6 + 1;
+        assert!(!proof.verify_turns(&rolls, &edited));
+
+        let mut wrong_secret = proof.clone();
+        wrong_secret.secrets[1] = "other".to_string();
+        assert!(!wrong_secret.verify(&rolls));
+        let mut wrong_nonce = proof.clone();
+        wrong_nonce.nonces[0] = random_secret();
+        assert!(!wrong_nonce.verify(&rolls));
+    }
+
+    #[test]
+    fn resume_continues_sequence() {
+        let mut full = FairDice::new(["a".to_string(), "b".to_string()]);
+        for _ in 0..5 {
+            full.roll_die();
+        }
+        let secrets = ["a".to_string(), "b".to_string()];
+        let mut resumed = FairDice::resume(secrets, full.nonces().clone(), 5);
+        assert_eq!(resumed.roll_die(), full.roll_die());
+    }
+}
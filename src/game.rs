@@ -1,20 +1,40 @@
 use crate::utils::*;
 use chrono::Local;
+use cli_backgammon::{
+    analysis::{self, Mistake, Turn, TurnAnalysis},
+    bearoff::{self, BearoffDatabase, WithBearoff},
+    bot::{self, Difficulty},
+    cube::{self, CubeState, MAX_CUBE_VALUE, Ownership},
+    dice::{DiceSource, ScriptedDice, SeededDice},
+    fair_dice::{self, FairDice, FairProof},
+    gnubg::{self, MatchId},
+    mat::{MatGame, Match},
+    network::{self, Network},
+    notation,
+    position::{BLACK, Position, WHITE, opponent},
+    replay::{Action, Record, Replay, Snapshot},
+    rules::{Move, Play, dice_from_roll, legal_moves},
+    search::{self, Evaluator, Heuristic, RankedPlay, SearchConfig},
+    sgf::{Annotation, Collection, Mark, SgfGame},
+    xgid,
+};
 use crossterm::{
     cursor::{Hide, Show},
-    event::{Event, KeyCode, read},
+    event::{Event, KeyCode, poll, read},
     execute,
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use rand::Rng;
 use std::{
+    cmp::Ordering,
     fs::{self, File, OpenOptions, read_to_string},
     io::{BufRead, BufReader, Error, ErrorKind, Write, stdin, stdout},
-    u32,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
 };
 
-const WHITE: u8 = 0;
-const BLACK: u8 = 1;
+const CENTERED: u8 = 2; // cube owner when nobody has doubled yet
 const BOARD_OFFSET: u16 = 1;
 const MAX_CHECKERS_DRAWN: u16 = 5;
 const LINE_NUMBER_1: u16 = 17;
@@ -23,26 +43,100 @@ const LINE_NUMBER_3: u16 = 19;
 const LINE_NUMBER_4: u16 = 20;
 const LINE_NUMBER_5: u16 = 21;
 const LINE_NUMBER_6: u16 = 22;
+const PIP_LINE: u16 = 16; // below the board
+const HINT_LINE: u16 = 13; // next to the board, below the cube and match score
+const HINT_COUNT: usize = 3;
+const ANNOTATION_WIDTH: usize = 100;
+const MAT_EXTENSION: &str = ".mat";
+const SGF_EXTENSION: &str = ".sgf";
+const BOT_DELAY: Duration = Duration::from_millis(700); // pause so computer moves can be followed
+
+// dice typed in by the players, e.g. when playing over a physical board
+#[derive(Debug)]
+struct ManualDice;
+
+impl DiceSource for ManualDice {
+    fn roll_die(&mut self) -> Option<u8> {
+        print_message(0, LINE_NUMBER_4, "Enter die (1-6), ESC - cancel:");
+        loop {
+            if let Ok(Event::Key(key_event)) = read() {
+                match key_event.code {
+                    KeyCode::Char(c @ '1'..='6') => {
+                        clear_line(LINE_NUMBER_4);
+                        return Some(c as u8 - b'0');
+                    }
+                    KeyCode::Esc => {
+                        clear_line(LINE_NUMBER_4);
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct PreviousMovesBuffer {
-    board: [u8; 24],
-    turn: u8,
-    bar: [u8; 2],
-    tray: [u8; 2],
+    position: Position,
+    cube_value: u32,
+    cube_owner: u8,
+}
+
+impl PreviousMovesBuffer {
+    fn from_snapshot(snapshot: &Snapshot) -> Self {
+        Self {
+            position: snapshot.position,
+            cube_value: snapshot.cube_value,
+            cube_owner: snapshot.cube_owner.unwrap_or(CENTERED),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            position: self.position,
+            cube_value: self.cube_value,
+            cube_owner: (self.cube_owner != CENTERED).then_some(self.cube_owner),
+        }
+    }
+}
+
+// a turn of the current game, `snapshot` is the last previous move before it
+#[derive(Debug)]
+pub struct RecordedTurn {
+    snapshot: usize,
+    turn: Turn,
 }
 
 #[derive(Debug)]
 pub struct Game {
-    board: [u8; 24],
-    turn: u8,
+    position: Position,
     roll_result: Vec<u8>,
-    moves: Vec<(usize, usize)>,
+    dice: Box<dyn DiceSource>,
+    start_seed: Option<(u64, u64)>, // dice seed when the current game started, for replays
+    rolled_dice: Vec<u8>,           // every die rolled in the current game
+    is_fair: bool,                  // new commit-reveal dice for every game
+    replay_proof: Option<FairProof>,
+    moves: Vec<Move>,
+    plays: Vec<Play>,  // every legal full play for the current roll
+    played: Vec<Move>, // moves already made this turn
     previous_moves: Vec<PreviousMovesBuffer>,
-    bar: [u8; 2],
-    tray: [u8; 2],
-    is_over: bool,    // is certain game finished
-    is_running: bool, // is whole program running
+    turns: Vec<RecordedTurn>,
+    cube_actions: Vec<(usize, Record)>, // with the number of turns before them
+    finished_games: Vec<MatGame>,       // recorded games of the current match, for exports
+    annotations: Vec<(usize, Annotation)>, // per record of an .sgf replay, with its last snapshot
+    analysis: Vec<TurnAnalysis>,        // one per turn once the game was analyzed
+    cube_value: u32,
+    cube_owner: u8,    // WHITE, BLACK or CENTERED
+    match_length: u32, // 0 when playing single games
+    score: [u32; 2],
+    is_crawford: bool, // no doubling in the game after someone reaches match_length - 1
+    is_post_crawford: bool,
+    bots: [Option<Difficulty>; 2], // computer players, None for humans
+    bearoff: Option<BearoffDatabase>, // loaded from saves/bearoff.db when it was generated
+    preset_roll: Option<(u8, u8)>, // dice set in the editor, used for the next roll
+    is_over: bool,                 // is certain game finished
+    is_running: bool,              // is whole program running
 }
 
 impl Game {
@@ -51,38 +145,31 @@ impl Game {
         execute!(stdout(), EnterAlternateScreen, Hide).unwrap();
 
         Self {
-            board: [
-                2 + 15,
-                0,
-                0,
-                0,
-                0,
-                5,
-                0,
-                3,
-                0,
-                0,
-                0,
-                5 + 15,
-                5,
-                0,
-                0,
-                0,
-                3 + 15,
-                0,
-                5 + 15,
-                0,
-                0,
-                0,
-                0,
-                2,
-            ], // white takes 1-15, black takes 16-3
-            turn: WHITE, // rust doesn't tolerate uninitialized fields, needed
+            position: Position::new(),
             roll_result: Vec::new(),
+            dice: Box::new(SeededDice::random()),
+            start_seed: None,
+            rolled_dice: Vec::new(),
+            is_fair: false,
+            replay_proof: None,
             moves: Vec::new(),
+            plays: Vec::new(),
+            played: Vec::new(),
             previous_moves: Vec::new(),
-            bar: [0, 0],
-            tray: [0, 0],
+            turns: Vec::new(),
+            cube_actions: Vec::new(),
+            finished_games: Vec::new(),
+            annotations: Vec::new(),
+            analysis: Vec::new(),
+            cube_value: 1,
+            cube_owner: CENTERED,
+            match_length: 0,
+            score: [0, 0],
+            is_crawford: false,
+            is_post_crawford: false,
+            bots: [None, None],
+            bearoff: BearoffDatabase::load(bearoff::DEFAULT_PATH).ok(),
+            preset_roll: None,
             is_over: false,
             is_running: true,
         }
@@ -94,10 +181,10 @@ impl Game {
         if let Ok(content) = read_to_string("saves/leaderboard.txt") {
             for line in content.lines() {
                 let mut parts = line.split_whitespace();
-                if let (Some(nick), Some(score)) = (parts.next(), parts.next()) {
-                    if let Ok(score) = score.parse::<u32>() {
-                        leaderboard.push((nick.to_string(), score));
-                    }
+                if let (Some(nick), Some(score)) = (parts.next(), parts.next())
+                    && let Ok(score) = score.parse::<u32>()
+                {
+                    leaderboard.push((nick.to_string(), score));
                 }
             }
         }
@@ -109,7 +196,7 @@ impl Game {
             ));
         }
 
-        leaderboard.sort_by(|a, b| b.1.cmp(&a.1));
+        leaderboard.sort_by_key(|entry| std::cmp::Reverse(entry.1));
 
         clear_screen();
         let mut i = 0;
@@ -121,19 +208,17 @@ impl Game {
         }
         print_message(0, i + 2, "Press anything to go back, q to quit");
 
-        if let Ok(event) = read() {
-            if let Event::Key(key_event) = event {
-                match key_event.code {
-                    KeyCode::Char('q') => self.quit(),
-                    _ => {}
-                }
-            }
+        if let Ok(event) = read()
+            && let Event::Key(key_event) = event
+            && let KeyCode::Char('q') = key_event.code
+        {
+            self.quit()
         }
 
         Ok(())
     }
 
-    fn get_nick() -> std::io::Result<String> {
+    fn get_line() -> std::io::Result<String> {
         terminal::disable_raw_mode()?;
 
         move_cursor(0, LINE_NUMBER_4);
@@ -145,8 +230,29 @@ impl Game {
         Ok(nick)
     }
 
-    fn update_leaderboard() -> std::io::Result<()> {
-        let nick = Self::get_nick()?;
+    // like get_line, but the typed text is shown as stars
+    fn get_hidden_line() -> String {
+        move_cursor(0, LINE_NUMBER_4);
+        let mut line = String::new();
+        loop {
+            if let Ok(Event::Key(key_event)) = read() {
+                match key_event.code {
+                    KeyCode::Enter => break,
+                    KeyCode::Backspace => {
+                        line.pop();
+                    }
+                    KeyCode::Char(c) => line.push(c),
+                    _ => continue,
+                }
+                print_message(0, LINE_NUMBER_4, &"*".repeat(line.chars().count()));
+            }
+        }
+        clear_line(LINE_NUMBER_4);
+        line
+    }
+
+    fn update_leaderboard(points: u32) -> std::io::Result<()> {
+        let nick = Self::get_line()?;
         let mut leaderboard = Vec::new();
         let mut is_found = false;
 
@@ -158,7 +264,7 @@ impl Game {
                 if let (Some(file_nick), Some(score)) = (parts.next(), parts.next()) {
                     if nick == file_nick {
                         if let Ok(score) = score.parse::<u32>() {
-                            leaderboard.push(format!("{} {}", nick, score + 1));
+                            leaderboard.push(format!("{} {}", nick, score + points));
                             is_found = true;
                         }
                     } else {
@@ -169,7 +275,7 @@ impl Game {
         }
 
         if !is_found {
-            leaderboard.push(format!("{} {}", nick, 1));
+            leaderboard.push(format!("{} {}", nick, points));
         }
 
         let mut file = OpenOptions::new()
@@ -191,24 +297,62 @@ impl Game {
         let mut file = File::create(&filename)?;
 
         // board
-        for field in self.board {
+        for field in self.position.board_values() {
             write!(file, "{} ", field)?;
         }
         writeln!(file)?;
 
         // next turn
-        writeln!(file, "{}", self.turn)?;
+        writeln!(file, "{}", self.position.turn)?;
 
         // bar
-        for field in self.bar {
+        for field in self.position.bar {
             write!(file, "{} ", field)?;
         }
         writeln!(file)?;
 
         // tray
-        for field in self.tray {
+        for field in self.position.tray {
             write!(file, "{} ", field)?;
         }
+        writeln!(file)?;
+
+        // cube
+        writeln!(file, "cube {} {}", self.cube_value, self.cube_owner)?;
+
+        // dice
+        if let Some((seed, rolled)) = self.dice.seed() {
+            writeln!(file, "dice {seed} {rolled}")?;
+        }
+        // the secrets stay hidden until the game is over, players type them again to go on
+        if let Some(proof) = self.dice.fair_proof() {
+            let [white, black] = &proof.commitments;
+            writeln!(file, "fair {white} {black} {}", proof.rolled)?;
+            // earlier dice of this game, so its replay still verifies
+            write!(file, "rolls")?;
+            for die in &self.rolled_dice {
+                write!(file, " {die}")?;
+            }
+            writeln!(file)?;
+        }
+
+        // dice set in the editor and not rolled yet
+        if let Some((dice_1, dice_2)) = self.preset_roll {
+            writeln!(file, "roll {dice_1} {dice_2}")?;
+        }
+
+        // match
+        if self.match_length > 0 {
+            writeln!(
+                file,
+                "match {} {} {} {} {}",
+                self.match_length,
+                self.score[WHITE as usize],
+                self.score[BLACK as usize],
+                self.is_crawford as u8,
+                self.is_post_crawford as u8
+            )?;
+        }
 
         Ok(())
     }
@@ -218,38 +362,63 @@ impl Game {
         let filename = Local::now()
             .format("saves/replays/%H%M%S_%m%m%Y")
             .to_string();
+        let Some(replay) = self.replay() else {
+            return Ok(());
+        };
         let mut file = File::create(&filename)?;
+        write!(file, "{replay}")
+    }
 
-        for mv in &self.previous_moves {
-            // board
-            for field in mv.board {
-                write!(file, "{} ", field)?;
-            }
-            writeln!(file)?;
-
-            // next turn
-            writeln!(file, "{}", mv.turn)?;
-
-            // bar
-            for field in mv.bar {
-                write!(file, "{} ", field)?;
+    // the current game, None when it wasn't recorded from its start
+    fn replay(&self) -> Option<Replay> {
+        let start = self.previous_moves.first()?;
+        let mut tags = Vec::new();
+        if let Some((seed, rolled)) = self.start_seed {
+            tags.push(format!("dice {seed} {rolled}"));
+        }
+        let rolls: Vec<String> = self.rolled_dice.iter().map(u8::to_string).collect();
+        tags.push(format!("rolls {}", rolls.join(" ")));
+        if let Some(proof) = self.dice.fair_proof() {
+            let [white, black] = &proof.commitments;
+            tags.push(format!("commit {white} {black}"));
+            let [white, black] = &proof.secrets;
+            tags.push(format!("reveal {white} {black}"));
+            // games resumed from saves of older versions have no nonces
+            if let [white, black] = &proof.nonces
+                && !white.is_empty()
+            {
+                tags.push(format!("salt {white} {black}"));
             }
-            writeln!(file)?;
+        }
+        Some(Replay {
+            start: start.snapshot(),
+            tags,
+            records: self.records(),
+        })
+    }
 
-            // tray
-            for field in mv.tray {
-                write!(file, "{} ", field)?;
+    // the game so far, cube actions go before the turn that followed them
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut cube_actions = self.cube_actions.iter().peekable();
+        for (index, recorded) in self.turns.iter().enumerate() {
+            while let Some((_, record)) = cube_actions.next_if(|(turns, _)| *turns <= index) {
+                records.push(record.clone());
             }
-            writeln!(file)?;
+            let turn = &recorded.turn;
+            records.push(Record {
+                color: turn.position.turn,
+                action: Action::Roll(turn.dice, turn.play.clone()),
+            });
         }
-
-        Ok(())
+        records.extend(cube_actions.map(|(_, record)| record.clone()));
+        records
     }
 
-    fn parse_line_to_vec(
+    fn parse_line_to_vec<T: FromStr>(
         reader: &mut BufReader<File>,
         line: &mut String,
-    ) -> std::io::Result<Vec<u8>> {
+    ) -> std::io::Result<Vec<T>> {
         line.clear();
         if reader.read_line(line)? == 0 {
             return Ok(Vec::new());
@@ -260,6 +429,31 @@ impl Game {
             .collect())
     }
 
+    // optional lines are tagged with a keyword, e.g. "cube 2 0"
+    fn parse_tagged_line(line: &str) -> Option<(&str, Vec<&str>)> {
+        let mut parts = line.split_whitespace();
+        let tag = parts.next()?;
+        if !tag.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        Some((tag, parts.collect()))
+    }
+
+    fn parse_numbers(words: &[&str]) -> Vec<u64> {
+        words.iter().filter_map(|x| x.parse().ok()).collect()
+    }
+
+    fn apply_cube(values: &[u64]) -> Option<(u32, u8)> {
+        if let [value, owner] = values
+            && let Ok(value) = u32::try_from(*value)
+            && cube::is_valid_cube_value(value)
+            && *owner <= CENTERED as u64
+        {
+            return Some((value, *owner as u8));
+        }
+        None
+    }
+
     fn get_filename(&mut self, dir_path: &str) -> std::io::Result<Option<String>> {
         clear_screen();
         print_message(0, 0, "Select save to read from:");
@@ -272,12 +466,16 @@ impl Game {
             }
         }
         filenames.sort();
-        for i in 0..filenames.len() {
-            let message = format!("{}. {}", i + 1, filenames[i]);
+        if filenames.is_empty() {
+            print_temp_message(0, 1, "No files found", 1000);
+            return Ok(None);
+        }
+        for (i, filename) in filenames.iter().enumerate() {
+            let message = format!("{}. {}", i + 1, filename);
             print_message(0, (i + 1) as u16, &message);
         }
 
-        let mut cursor = 0 as u16;
+        let mut cursor = 0_u16;
         let lenght = filenames.len() as u16;
         while self.is_running {
             for j in 0..filenames.len() {
@@ -287,16 +485,16 @@ impl Game {
                     print_at(20, cursor + 1, "<");
                 }
             }
-            if let Ok(event) = read() {
-                if let Event::Key(key_event) = event {
-                    match key_event.code {
-                        KeyCode::Up => cursor = if cursor == 0 { lenght - 1 } else { cursor - 1 },
-                        KeyCode::Down => cursor = (cursor + 1) % lenght,
-                        KeyCode::Enter => return Ok(Some(filenames[cursor as usize].clone())),
-                        KeyCode::Esc => break,
-                        KeyCode::Char('q') => self.quit(),
-                        _ => {}
-                    }
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                match key_event.code {
+                    KeyCode::Up => cursor = if cursor == 0 { lenght - 1 } else { cursor - 1 },
+                    KeyCode::Down => cursor = (cursor + 1) % lenght,
+                    KeyCode::Enter => return Ok(Some(filenames[cursor as usize].clone())),
+                    KeyCode::Esc => break,
+                    KeyCode::Char('q') => self.quit(),
+                    _ => {}
                 }
             }
         }
@@ -310,22 +508,20 @@ impl Game {
             let mut reader = BufReader::new(file);
             let mut line = String::new();
 
-            // Read board
+            // Read board, older saves store black as count + 15
             let temp = Self::parse_line_to_vec(&mut reader, &mut line)?;
-            if temp.len() != 24 {
+            let Some(board) = Position::parse_board(&temp) else {
                 return Err(Error::new(
                     ErrorKind::InvalidData,
-                    "Expected 24 elements for board in save file",
+                    "Expected 24 valid elements for board in save file",
                 ));
-            }
-            for (i, field) in temp.iter().enumerate() {
-                self.board[i] = *field;
-            }
+            };
+            self.position.board = board;
 
             // Read turn
             line.clear();
             reader.read_line(&mut line)?;
-            self.turn = line.trim().parse().unwrap_or(WHITE);
+            self.position.turn = line.trim().parse().unwrap_or(WHITE);
 
             // Read bar
             let temp = Self::parse_line_to_vec(&mut reader, &mut line)?;
@@ -336,7 +532,7 @@ impl Game {
                 ));
             }
             for (i, field) in temp.iter().enumerate() {
-                self.bar[i] = *field;
+                self.position.bar[i] = *field;
             }
 
             // Read tray
@@ -348,87 +544,379 @@ impl Game {
                 ));
             }
             for (i, field) in temp.iter().enumerate() {
-                self.tray[i] = *field;
+                self.position.tray[i] = *field;
+            }
+
+            // Read optional tagged lines, older saves end here
+            self.cube_value = 1;
+            self.cube_owner = CENTERED;
+            self.reset_match();
+            self.rolled_dice.clear();
+            self.preset_roll = None;
+            // not recorded, the earlier moves aren't in the save
+            self.previous_moves.clear();
+            self.turns.clear();
+            self.cube_actions.clear();
+            for line in reader.lines() {
+                let line = line?;
+                let Some((tag, words)) = Self::parse_tagged_line(&line) else {
+                    continue;
+                };
+                let values = Self::parse_numbers(&words);
+                match tag {
+                    "cube" => {
+                        let Some((value, owner)) = Self::apply_cube(&values) else {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "Invalid cube in save file",
+                            ));
+                        };
+                        self.cube_value = value;
+                        self.cube_owner = owner;
+                    }
+                    "match" => {
+                        if let [length, white, black, crawford, post_crawford] = values[..] {
+                            let (length, score) =
+                                match (length.try_into(), white.try_into(), black.try_into()) {
+                                    (Ok(length), Ok(white), Ok(black))
+                                        if gnubg::is_valid_score(length, [white, black]) =>
+                                    {
+                                        (length, [white, black])
+                                    }
+                                    _ => {
+                                        return Err(Error::new(
+                                            ErrorKind::InvalidData,
+                                            "Invalid match score in save file",
+                                        ));
+                                    }
+                                };
+                            self.match_length = length;
+                            self.score = score;
+                            self.is_crawford = crawford == 1;
+                            self.is_post_crawford = post_crawford == 1;
+                        }
+                    }
+                    "dice" => {
+                        if let [seed, rolled] = values[..] {
+                            let Some(dice) = SeededDice::resume(seed, rolled) else {
+                                return Err(Error::new(
+                                    ErrorKind::InvalidData,
+                                    "Too many dice rolled in save file",
+                                ));
+                            };
+                            self.dice = Box::new(dice);
+                        }
+                    }
+                    "fair" => {
+                        if let [white, black, rolled] = words[..]
+                            && let Ok(rolled) = rolled.parse()
+                        {
+                            if fair_dice::is_commitment(white) && fair_dice::is_commitment(black) {
+                                if !self.resume_fair_dice([white, black], rolled) {
+                                    return Err(Error::new(
+                                        ErrorKind::InvalidData,
+                                        "Wrong fair dice secret",
+                                    ));
+                                }
+                            } else {
+                                // older saves stored the secrets themselves, without nonces
+                                let secrets = [white.to_string(), black.to_string()];
+                                let dice = FairDice::resume(secrets, Default::default(), rolled);
+                                self.dice = Box::new(dice);
+                                self.is_fair = true;
+                            }
+                        }
+                    }
+                    "rolls" => self.rolled_dice = values.iter().map(|&x| x as u8).collect(),
+                    "roll" => {
+                        if let [dice_1 @ 1..=6, dice_2 @ 1..=6] = values[..] {
+                            self.preset_roll = Some((dice_1 as u8, dice_2 as u8));
+                        }
+                    }
+                    _ => {}
+                }
             }
         } else {
-            return Err(Error::new(ErrorKind::Other, "")); // to simplify returned value
+            return Err(Error::other("")); // to simplify returned value
         }
         Ok(())
     }
 
-    fn load_replay(&mut self) -> std::io::Result<()> {
-        if let Some(filename) = self.get_filename("saves/replays")? {
-            let path = format!("saves/replays/{filename}");
-            let file = File::open(&path)?;
-            let mut reader = BufReader::new(file);
-            let mut line = String::new();
-            self.previous_moves.clear();
-
-            loop {
-                // Read board
-                let board = Self::parse_line_to_vec(&mut reader, &mut line)?;
-                if board.len() != 24 {
-                    break;
+    fn load_replay(&mut self) -> std::io::Result<Replay> {
+        let Some(filename) = self.get_filename("saves/replays")? else {
+            return Err(Error::other("")); // to simplify returned value
+        };
+        let path = format!("saves/replays/{filename}");
+        let content = read_to_string(&path)?;
+        self.annotations.clear();
+        let replay = if filename.ends_with(MAT_EXTENSION) {
+            self.read_mat_game(&content)?
+        } else if filename.ends_with(SGF_EXTENSION) {
+            self.read_sgf_game(&content)?
+        } else {
+            // snapshot replays from older versions are converted as they load
+            Replay::parse(&content)
+                .or_else(|| Replay::convert(&content))
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unreadable replay"))?
+        };
+        let Some((snapshots, turns)) = replay.rebuild() else {
+            return Err(Error::new(ErrorKind::InvalidData, "impossible move"));
+        };
+        self.previous_moves = snapshots
+            .iter()
+            .map(PreviousMovesBuffer::from_snapshot)
+            .collect();
+        self.turns = turns
+            .into_iter()
+            .map(|(snapshot, turn)| RecordedTurn { snapshot, turn })
+            .collect();
+        self.analysis.clear();
+        self.start_seed = None;
+        self.rolled_dice.clear();
+        self.replay_proof = None;
+
+        for line in &replay.tags {
+            let Some((tag, words)) = Self::parse_tagged_line(line) else {
+                continue;
+            };
+            let values = Self::parse_numbers(&words);
+            match (tag, &values[..]) {
+                ("dice", &[seed, rolled]) => self.start_seed = Some((seed, rolled)),
+                ("rolls", _) => self.rolled_dice = values.iter().map(|&x| x as u8).collect(),
+                ("commit" | "reveal" | "salt", _) if words.len() == 2 => {
+                    let proof = self.replay_proof.get_or_insert_with(|| FairProof {
+                        commitments: Default::default(),
+                        secrets: Default::default(),
+                        nonces: Default::default(),
+                        rolled: 0,
+                    });
+                    let words = [words[0].to_string(), words[1].to_string()];
+                    match tag {
+                        "commit" => proof.commitments = words,
+                        "reveal" => proof.secrets = words,
+                        _ => proof.nonces = words,
+                    }
                 }
+                _ => {}
+            }
+        }
+        Ok(replay)
+    }
 
-                // Read turn
-                line.clear();
-                reader.read_line(&mut line)?;
-                let turn = line.trim().parse().unwrap_or(WHITE);
+    // one game of a .mat file, its match score is shown while it's replayed
+    fn read_mat_game(&mut self, content: &str) -> std::io::Result<Replay> {
+        let Some(mut played) = Match::parse(content) else {
+            return Err(Error::new(ErrorKind::InvalidData, "unreadable match"));
+        };
+        if played.games.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "no games"));
+        }
+        let game = played
+            .games
+            .swap_remove(self.pick_game(played.games.len())?);
+        self.match_length = played.length;
+        self.score = game.score;
+        Ok(game.replay)
+    }
 
-                // Read bar
-                let bar = Self::parse_line_to_vec(&mut reader, &mut line)?;
-                if bar.len() != 2 {
-                    break;
-                }
+    // one game of an .sgf file with its comments and marks
+    fn read_sgf_game(&mut self, content: &str) -> std::io::Result<Replay> {
+        let Some(mut collection) = Collection::parse(content) else {
+            return Err(Error::new(ErrorKind::InvalidData, "unreadable sgf"));
+        };
+        let game = collection
+            .games
+            .swap_remove(self.pick_game(collection.games.len())?);
+        self.match_length = collection.length;
+        self.score = game.score;
+        let mut annotations = game.annotations.into_iter();
+        self.annotations = game
+            .replay
+            .record_snapshots()
+            .into_iter()
+            .map(|snapshot| (snapshot, annotations.next().unwrap_or_default()))
+            .collect();
+        Ok(game.replay)
+    }
 
-                // Read tray
-                let tray = Self::parse_line_to_vec(&mut reader, &mut line)?;
-                if tray.len() != 2 {
-                    break;
-                }
+    // index of the game to replay from a file with `count` games
+    fn pick_game(&mut self, count: usize) -> std::io::Result<usize> {
+        if count <= 1 {
+            return Ok(0);
+        }
+        clear_screen();
+        let prompt = format!("Enter game number (1-{count}):");
+        match self.get_number(&prompt) {
+            Some(number) if (1..=count).contains(&(number as usize)) => Ok(number as usize - 1),
+            _ => Err(Error::other("")),
+        }
+    }
 
-                self.previous_moves.push(PreviousMovesBuffer {
-                    board: board.try_into().unwrap(),
-                    turn,
-                    bar: bar.try_into().unwrap(),
-                    tray: tray.try_into().unwrap(),
-                });
+    // writes games to the replays folder as a .mat or .sgf file, asking for the players' names
+    fn export_games(&mut self, length: u32, games: Vec<SgfGame>, extension: &str) {
+        let mut opening = Position::new();
+        let from_opening = games.iter().all(|game| {
+            opening.turn = game.replay.start.position.turn;
+            (game.replay.start.position, game.replay.start.cube_value) == (opening, 1)
+        });
+        let has_all_dice = games.iter().flat_map(|game| &game.replay.records).all(|record| {
+            !matches!(record.action, Action::Roll((die_1, die_2), _) if die_1 == 0 || die_2 == 0)
+        });
+        let problem = if !has_all_dice {
+            Some("Games with unknown dice can't be exported")
+        } else if extension == MAT_EXTENSION && !from_opening {
+            Some(".mat games have to start from the opening position")
+        } else {
+            None
+        };
+        if let Some(message) = problem {
+            print_temp_message(0, LINE_NUMBER_4, message, 1500);
+            return;
+        }
+        let mut names = [String::new(), String::new()];
+        for (name, color) in names.iter_mut().zip(["White", "Black"]) {
+            let prompt = format!("{color}'s player name (Enter for {color}):");
+            print_message(0, LINE_NUMBER_3, &prompt);
+            let line = Self::get_line().unwrap_or_default();
+            clear_line(LINE_NUMBER_4);
+            *name = match line.trim() {
+                "" => color.to_string(),
+                line => line.to_string(),
+            };
+        }
+        clear_line(LINE_NUMBER_3);
+
+        let content = if extension == MAT_EXTENSION {
+            let games = games
+                .into_iter()
+                .map(|game| MatGame {
+                    score: game.score,
+                    replay: game.replay,
+                })
+                .collect();
+            Match {
+                names,
+                length,
+                games,
+            }
+            .to_string()
+        } else {
+            Collection {
+                names,
+                length,
+                games,
             }
+            .to_string()
+        };
+        let filename = Local::now()
+            .format("saves/replays/%H%M%S_%m%m%Y")
+            .to_string();
+        let saved = fs::create_dir_all("saves/replays")
+            .and_then(|()| fs::write(filename + extension, content));
+        let message = if saved.is_ok() {
+            format!("Exported the {extension} file")
         } else {
-            return Err(Error::new(ErrorKind::Other, "")); // to simplify returned value
+            format!("Couldn't write the {extension} file")
+        };
+        print_temp_message(0, LINE_NUMBER_4, &message, 1000);
+    }
+
+    // the replayed game's annotations for an .sgf file, analyzed turns without one get
+    // their rating
+    fn export_annotations(&self, replay: &Replay) -> Vec<Annotation> {
+        let mut analyzed = self.turns.iter().zip(&self.analysis);
+        let mut annotations = Vec::new();
+        for (index, record) in replay.records.iter().enumerate() {
+            let mut annotation = self
+                .annotations
+                .get(index)
+                .map(|(_, annotation)| annotation.clone())
+                .unwrap_or_default();
+            if let Action::Roll((die_1, die_2), _) = record.action
+                && die_1 > 0
+                && die_2 > 0
+                && let Some((recorded, turn)) = analyzed.next()
+                && annotation.is_empty()
+                && !turn.is_forced
+            {
+                annotation.mark = turn.mistake().map(|mistake| match mistake {
+                    Mistake::Blunder => Mark::VeryBad,
+                    Mistake::Error => Mark::Bad,
+                    Mistake::Inaccuracy => Mark::Doubtful,
+                });
+                annotation.comment = Self::analysis_text(recorded, turn);
+            }
+            annotations.push(annotation);
         }
-        Ok(())
+        annotations
     }
 
     fn record_move(&mut self) {
         self.previous_moves.push(PreviousMovesBuffer {
-            board: self.board,
-            turn: self.turn,
-            bar: self.bar,
-            tray: self.tray,
+            position: self.position,
+            cube_value: self.cube_value,
+            cube_owner: self.cube_owner,
         });
     }
 
+    fn record_cube_action(&mut self, color: u8, action: Action) {
+        let record = Record { color, action };
+        self.cube_actions.push((self.turns.len(), record));
+    }
+
+    // games loaded from a save aren't recorded, they don't have their earlier moves
+    fn is_recording(&self) -> bool {
+        !self.previous_moves.is_empty()
+    }
+
+    // a pasted position can't be reached by moves, so the replay starts again from it
+    fn restart_recording(&mut self) {
+        self.previous_moves.clear();
+        self.turns.clear();
+        self.cube_actions.clear();
+        self.record_move();
+    }
+
+    fn record_turn_move(&mut self, mv: Move) {
+        if let Some(recorded) = self.turns.last_mut() {
+            recorded.turn.play.push(mv);
+        }
+    }
+
     fn show_move(&mut self, index: usize) {
         if let Some(mv) = self.previous_moves.get(index) {
-            self.board = mv.board;
-            self.turn = mv.turn;
-            self.bar = mv.bar;
-            self.tray = mv.tray;
+            self.position = mv.position;
+            self.cube_value = mv.cube_value;
+            self.cube_owner = mv.cube_owner;
         }
 
         self.draw_board();
         print_message(
             0,
             LINE_NUMBER_1,
-            "←/→ - one move back/forward, s/e - first/last move",
+            "←/→ - move back/forward, s/e - first/last move, A)nalyze, B)lunder, X) .mat, G) .sgf export",
         );
+        if let Some((seed, rolled)) = self.start_seed {
+            let message = format!("Dice seed: {seed}, starting after {rolled} dice");
+            print_message(0, LINE_NUMBER_4, &message);
+        }
+        if let Some(proof) = &self.replay_proof {
+            let dice: Vec<(u8, u8)> = self
+                .turns
+                .iter()
+                .map(|recorded| recorded.turn.dice)
+                .collect();
+            if proof.verify_turns(&self.rolled_dice, &dice) {
+                print_message(0, LINE_NUMBER_5, "Fair dice: all rolls verified");
+            } else {
+                print_message(0, LINE_NUMBER_5, "Fair dice: verification FAILED");
+            }
+        }
         if index == 0 {
             print_message(0, LINE_NUMBER_2, "Beggining");
             clear_line(LINE_NUMBER_3);
         } else {
-            if self.turn == WHITE {
+            if self.position.turn == WHITE {
                 print_message(0, LINE_NUMBER_2, "White's turn");
             } else {
                 print_message(0, LINE_NUMBER_2, "Black's turn");
@@ -436,359 +924,215 @@ impl Game {
             let message = format!("Move number: {}", index);
             print_message(0, LINE_NUMBER_3, &message);
         }
+        if !self.analysis.is_empty() {
+            self.print_analysis_summary(60, HINT_LINE);
+            self.print_turn_analysis(index);
+        }
+        self.print_annotations(index);
     }
 
-    fn visualize_replay(&mut self) {
-        if self.load_replay().is_ok() {
-            let mut cursor = 0;
-            let last_index = self.previous_moves.len() - 1;
-            self.show_move(cursor);
-            while self.is_running {
-                if let Ok(event) = read() {
-                    if let Event::Key(key_event) = event {
-                        match key_event.code {
-                            KeyCode::Left => {
-                                if cursor > 0 {
-                                    cursor -= 1;
-                                    self.show_move(cursor);
-                                }
-                            }
-                            KeyCode::Right => {
-                                if cursor < last_index {
-                                    cursor += 1;
-                                    self.show_move(cursor);
-                                }
-                            }
-                            KeyCode::Char('s') => {
-                                cursor = 0;
-                                self.show_move(cursor);
-                            }
-                            KeyCode::Char('e') => {
-                                cursor = last_index;
-                                self.show_move(cursor);
-                            }
-                            KeyCode::Esc => return,
-                            KeyCode::Char('q') => self.quit(),
-                            _ => {}
-                        }
-                    }
-                }
+    // comments and marks of an .sgf replay for the actions that end at `index`
+    fn print_annotations(&self, index: usize) {
+        let notes: Vec<String> = self
+            .annotations
+            .iter()
+            .filter(|(snapshot, annotation)| *snapshot == index && !annotation.is_empty())
+            .map(|(_, annotation)| match annotation.mark {
+                Some(mark) => format!("[{}] {}", mark.name(), annotation.comment),
+                None => annotation.comment.clone(),
+            })
+            .collect();
+        if notes.is_empty() {
+            if !self.annotations.is_empty() {
+                clear_line(LINE_NUMBER_5);
             }
+            return;
         }
-        self.reset();
+        let text = notes.join(" | ").replace('\n', " ");
+        let text: String = text.chars().take(ANNOTATION_WIDTH).collect();
+        print_message(0, LINE_NUMBER_5, &text);
     }
 
-    fn which_color(&self, field: usize) -> Option<u8> {
-        if self.board[field - 1] >= 1 && self.board[field - 1] <= 15 {
-            return Some(WHITE);
-        } else if self.board[field - 1] >= 16 && self.board[field - 1] <= 30 {
-            return Some(BLACK);
-        }
-        None
+    // the turn the move at `index` belongs to
+    fn turn_at(&self, index: usize) -> Option<usize> {
+        self.turns
+            .iter()
+            .rposition(|recorded| recorded.snapshot < index)
     }
 
-    fn are_all_home(&self, color: u8) -> bool {
-        let mut checker_count = 0;
-        if color == WHITE {
-            for field in (1..=6).rev() {
-                if self.which_color(field) == Some(WHITE) {
-                    checker_count += self.board[field - 1];
-                }
-            }
-        } else {
-            for field in 19..=24 {
-                if self.which_color(field) == Some(BLACK) {
-                    checker_count += self.board[field - 1] - 15;
-                }
-            }
-        }
-
-        if checker_count == 15 - self.tray[self.turn as usize] {
-            return true;
-        }
-        false
+    // last move of the next blunder after `index`
+    fn next_blunder(&self, index: usize) -> Option<usize> {
+        self.turns
+            .iter()
+            .zip(&self.analysis)
+            .map(|(recorded, turn)| (recorded.snapshot + recorded.turn.play.len(), turn))
+            .find(|(end, turn)| *end > index && turn.mistake() == Some(Mistake::Blunder))
+            .map(|(end, _)| end.min(self.previous_moves.len() - 1))
     }
 
-    fn farthest_to_tray(&self, color: u8) -> Option<usize> {
-        if color == WHITE {
-            for field in (1..=6).rev() {
-                if self.which_color(field) == Some(WHITE) {
-                    return Some(field);
-                }
-            }
-        } else {
-            for field in 19..=24 {
-                if self.which_color(field) == Some(BLACK) {
-                    return Some(field);
-                }
-            }
-        }
-        None
+    fn analyze(&mut self) {
+        print_message(0, LINE_NUMBER_4, "Analyzing...");
+        let turns: Vec<Turn> = self
+            .turns
+            .iter()
+            .map(|recorded| recorded.turn.clone())
+            .collect();
+        let analysis = analysis::analyze_game(&turns, &SearchConfig::default(), &*self.evaluator());
+        self.analysis = analysis;
+        clear_line(LINE_NUMBER_4);
     }
 
-    fn validate_home(&self, source: usize, destination: usize) -> bool {
-        // 0 and 25 represent tray for white and black, respectively
-        let valid_destination = if self.turn == WHITE { 0 } else { 25 };
-        if destination != valid_destination || source < 1 || source > 24 {
-            return false;
-        }
-
-        // has checker on source field
-        if self.board[source - 1] == 0 {
-            return false;
+    fn print_analysis_summary(&self, x: u16, y: u16) {
+        print_at(x, y, &format!("{:<40}", "Analysis (error rate, B/E/I):"));
+        for (line, (color, name)) in [(WHITE, "White"), (BLACK, "Black")].into_iter().enumerate() {
+            let summary = analysis::summarize(&self.analysis, color);
+            let text = format!(
+                "{name}: {:.1}, {}/{}/{}",
+                summary.error_rate, summary.blunders, summary.errors, summary.inaccuracies
+            );
+            print_at(x, y + 1 + line as u16, &format!("{text:<40}"));
         }
+    }
 
-        // right color
-        if let Some(color) = self.which_color(source) {
-            if color != self.turn {
-                return false;
-            }
-        }
+    fn print_turn_analysis(&self, index: usize) {
+        let Some((recorded, turn)) = self
+            .turn_at(index)
+            .and_then(|turn| self.turns.get(turn).zip(self.analysis.get(turn)))
+        else {
+            clear_line(LINE_NUMBER_6);
+            return;
+        };
+        print_message(0, LINE_NUMBER_6, &Self::analysis_text(recorded, turn));
+    }
 
-        // right direction
-        if self.turn == WHITE {
-            if destination >= source {
-                return false;
-            }
+    fn analysis_text(recorded: &RecordedTurn, turn: &TurnAnalysis) -> String {
+        if turn.is_forced {
+            "Forced play".to_string()
+        } else if let Some(mistake) = turn.mistake() {
+            format!(
+                "{} (-{:.3}), best: {}",
+                mistake.name(),
+                turn.loss(),
+                notation::format_play(&recorded.turn.position, &turn.best_play)
+            )
         } else {
-            if destination <= source {
-                return false;
-            }
+            format!("Good play (-{:.3})", turn.loss())
         }
-        true
     }
 
-    fn validate_bar(&self, source: usize, destination: usize) -> bool {
-        // 25 and 0 represent bar for white and black, respectively
-        let valid_source = if self.turn == WHITE { 25 } else { 0 };
-        if source != valid_source || destination < 1 || destination > 24 {
-            return false;
-        }
-
-        // right color
-        if let Some(color) = self.which_color(destination) {
-            if color != self.turn {
-                if !(self.board[destination - 1] == 1 || self.board[destination - 1] == 16) {
-                    return false;
-                }
-            }
+    fn visualize_replay(&mut self) {
+        let loaded = self.load_replay();
+        if let Err(error) = &loaded
+            && error.kind() == ErrorKind::InvalidData
+        {
+            print_temp_message(0, 0, "Couldn't read the replay", 1000);
         }
-
-        // right direction
-        if self.turn == WHITE {
-            if destination >= source {
-                return false;
-            }
-        } else {
-            if destination <= source {
-                return false;
-            }
-        }
-        true
-    }
-
-    fn is_move_valid(&self, source: usize, destination: usize) -> bool {
-        if self.bar[self.turn as usize] > 0 {
-            return self.validate_bar(source, destination);
-        }
-
-        // withing board bounds
-        if source < 1 || source > 24 || destination < 1 || destination > 24 {
-            return false;
-        }
-
-        // has checker on source field
-        if self.board[source - 1] == 0 {
-            return false;
-        }
-
-        // right color
-        if let Some(color) = self.which_color(source) {
-            if color != self.turn {
-                return false;
-            }
-        }
-        if let Some(color) = self.which_color(destination) {
-            if color != self.turn {
-                if !(self.board[destination - 1] == 1 || self.board[destination - 1] == 16) {
-                    return false;
-                }
-            }
-        }
-
-        // right direction
-        if self.turn == WHITE {
-            if destination >= source {
-                return false;
-            }
-        } else {
-            if destination <= source {
-                return false;
-            }
-        }
-        true
-    }
-
-    fn add_moves_to_tray_dice(&mut self, destination: usize, mut dice: isize) {
-        if let Some(field) = self.farthest_to_tray(self.turn) {
-            if dice > (destination as i32 - field as i32).abs() as isize {
-                dice = (destination as i32 - field as i32).abs() as isize;
-            }
-        }
-        if self.turn == BLACK {
-            dice *= -1;
-        }
-        let src = destination as isize + dice;
-        if src >= 1 && src <= 24 && self.validate_home(src as usize, destination) {
-            self.moves.push((src as usize, destination));
-        }
-    }
-
-    fn add_moves_to_tray(&mut self) {
-        let destination: usize = if self.turn == WHITE { 0 } else { 25 };
-        let mut dice: isize = self.roll_result[0] as isize;
-        self.add_moves_to_tray_dice(destination, dice);
-
-        if self.roll_result.len() > 1 && self.roll_result[0] != self.roll_result[1] {
-            dice = self.roll_result[1] as isize;
-            self.add_moves_to_tray_dice(destination, dice);
-        }
-    }
-
-    fn add_moves_from_dice(&mut self, source: usize, mut dice: isize) {
-        if self.turn == WHITE {
-            dice *= -1;
-        }
-        let dest = source as isize + dice;
-        if dest >= 1 && dest <= 24 && self.is_move_valid(source, dest as usize) {
-            self.moves.push((source, dest as usize));
-        }
-    }
-
-    fn add_moves_from(&mut self, source: usize) {
-        let mut dice: isize = self.roll_result[0] as isize;
-        self.add_moves_from_dice(source, dice);
-
-        if self.roll_result.len() > 1 && self.roll_result[0] != self.roll_result[1] {
-            dice = self.roll_result[1] as isize;
-            self.add_moves_from_dice(source, dice);
-        }
-    }
-
-    fn generate_moves(&mut self) {
-        self.moves.clear();
-
-        // moves from bar first
-        if self.bar[self.turn as usize] > 0 {
-            let source: usize = if self.turn == WHITE { 25 } else { 0 };
-            self.add_moves_from(source);
-        } else {
-            // typical moves
-            for source in 1..=24 {
-                if let Some(color) = self.which_color(source) {
-                    if color == self.turn {
-                        self.add_moves_from(source);
+        if let Ok(replay) = loaded {
+            let mut cursor = 0;
+            let last_index = self.previous_moves.len() - 1;
+            self.show_move(cursor);
+            while self.is_running {
+                if let Ok(event) = read()
+                    && let Event::Key(key_event) = event
+                {
+                    match key_event.code {
+                        KeyCode::Left if cursor > 0 => {
+                            cursor -= 1;
+                            self.show_move(cursor);
+                        }
+                        KeyCode::Right if cursor < last_index => {
+                            cursor += 1;
+                            self.show_move(cursor);
+                        }
+                        KeyCode::Char('s') => {
+                            cursor = 0;
+                            self.show_move(cursor);
+                        }
+                        KeyCode::Char('e') => {
+                            cursor = last_index;
+                            self.show_move(cursor);
+                        }
+                        KeyCode::Char('a') => {
+                            self.analyze();
+                            self.show_move(cursor);
+                        }
+                        KeyCode::Char('b') => {
+                            if let Some(blunder) = self.next_blunder(cursor) {
+                                cursor = blunder;
+                                self.show_move(cursor);
+                            } else if self.analysis.is_empty() {
+                                print_temp_message(
+                                    0,
+                                    LINE_NUMBER_4,
+                                    "Analyze the game first",
+                                    1000,
+                                );
+                            } else {
+                                print_temp_message(0, LINE_NUMBER_4, "No more blunders", 1000);
+                            }
+                        }
+                        KeyCode::Char(key @ ('x' | 'g')) => {
+                            let game = SgfGame {
+                                score: self.score,
+                                annotations: self.export_annotations(&replay),
+                                replay: replay.clone(),
+                            };
+                            let extension = if key == 'x' {
+                                MAT_EXTENSION
+                            } else {
+                                SGF_EXTENSION
+                            };
+                            self.export_games(self.match_length, vec![game], extension);
+                            self.show_move(cursor);
+                        }
+                        KeyCode::Esc => break,
+                        KeyCode::Char('q') => self.quit(),
+                        _ => {}
                     }
                 }
             }
-
-            // moves to tray
-            if self.are_all_home(self.turn) {
-                self.add_moves_to_tray();
-            }
-        }
-
-        // sort and remove duplicates (needed for forced bear off)
-        self.moves.sort();
-        self.moves.dedup();
-    }
-
-    fn move_checker(&mut self, source: usize, destination: usize) {
-        // moves to tray / other moves
-        if self.are_all_home(self.turn) && (destination == 0 || destination == 25) {
-            self.tray[self.turn as usize] += 1;
-            // removing the roll if taking of was forced (smaller move than the greatest roll)
-            if let Some((index, &max)) = self
-                .roll_result
-                .iter()
-                .enumerate()
-                .max_by_key(|&(_, &val)| val)
-            {
-                let diff = (destination as i32 - source as i32).abs() as u8;
-                if !self.roll_result.contains(&diff) && max > diff {
-                    self.roll_result.remove(index);
-                }
-            }
-        } else {
-            // checker gets captured
-            if let Some(color) = self.which_color(destination) {
-                if color != self.turn {
-                    self.board[destination - 1] = 0;
-                    self.bar[color as usize] += 1;
-                }
-            }
-
-            // black moves to empty fields
-            if self.turn == BLACK && self.board[destination - 1] == 0 {
-                self.board[destination - 1] += 15;
-            }
-            self.board[destination - 1] += 1;
-        }
-
-        // moves from bar / other moves
-        if self.bar[self.turn as usize] != 0 {
-            self.bar[self.turn as usize] -= 1;
-        } else {
-            self.board[source - 1] -= 1;
-            // black moves single checker
-            if self.board[source - 1] == 15 {
-                self.board[source - 1] = 0;
-            }
         }
-
-        // removing the roll
-        if let Some(index) = self
-            .roll_result
-            .iter()
-            .position(|&x| x == ((destination as i32 - source as i32).abs()) as u8)
-        {
-            self.roll_result.remove(index);
-        }
-    }
-
-    fn roll() -> u8 {
-        let mut rng = rand::rng();
-        rng.random_range(1..=6)
+        self.reset();
+        self.reset_match();
     }
 
-    fn handle_roll(&mut self) {
+    // returns false if the dice source gave nothing, e.g. a finished script
+    fn handle_roll(&mut self) -> bool {
         self.roll_result.clear();
 
-        let dice_1 = Self::roll();
-        self.roll_result.push(dice_1);
-        let dice_2 = Self::roll();
-        self.roll_result.push(dice_2);
+        // fair dice all have to come from the secrets, so they never use a preset roll
+        let preset_roll = self.preset_roll.take();
+        let (dice_1, dice_2) = if let Some(roll) = preset_roll.filter(|_| !self.is_fair) {
+            roll
+        } else {
+            let (Some(dice_1), Some(dice_2)) = (self.dice.roll_die(), self.dice.roll_die()) else {
+                print_temp_message(0, LINE_NUMBER_4, "No dice rolled", 1000);
+                return false;
+            };
+            (dice_1, dice_2)
+        };
+        self.roll_result = dice_from_roll(dice_1, dice_2);
+        self.rolled_dice.extend([dice_1, dice_2]);
+        self.turns.push(RecordedTurn {
+            snapshot: self.previous_moves.len().saturating_sub(1),
+            turn: Turn {
+                position: self.position,
+                dice: (dice_1, dice_2),
+                play: Vec::new(),
+            },
+        });
 
         let dice_str = if dice_1 != dice_2 {
             format!("Result: {dice_1}, {dice_2}")
         } else {
-            self.roll_result.push(dice_1);
-            self.roll_result.push(dice_1);
             format!("Result: {dice_1}, {dice_1}, {dice_1}, {dice_1}")
         };
         print_message(0, LINE_NUMBER_5, &dice_str);
-    }
-
-    fn change_turn(&mut self) {
-        if self.turn == WHITE {
-            self.turn = BLACK;
-        } else {
-            self.turn = WHITE;
-        }
+        true
     }
 
     fn print_turn(&self) {
-        if self.turn == WHITE {
+        if self.position.turn == WHITE {
             print_message(0, LINE_NUMBER_2, "White's turn");
         } else {
             print_message(0, LINE_NUMBER_2, "Black's turn");
@@ -796,18 +1140,77 @@ impl Game {
     }
 
     fn print_moves(&self) {
-        let mut moves_str = self
+        let moves: Vec<String> = self
             .moves
             .iter()
-            .map(|(src, dst)| format!("{src}->{dst}"))
-            .collect::<Vec<String>>()
-            .join(", ");
-        moves_str = format!("Moves: {moves_str}");
+            .map(|&mv| notation::format_play(&self.position, &[mv]))
+            .collect();
+        let moves_str = format!("Moves: {}", moves.join(", "));
         print_message(0, LINE_NUMBER_6, &moves_str);
     }
 
+    // engine used for hints and analysis, exact in bear-off races when the database is there
+    fn evaluator(&self) -> Box<dyn Evaluator + '_> {
+        match &self.bearoff {
+            Some(database) => Box::new(WithBearoff {
+                database,
+                fallback: &Heuristic,
+            }),
+            None => Box::new(Heuristic),
+        }
+    }
+
+    fn rank_plays(&self, position: &Position, dice: &[u8]) -> Vec<RankedPlay> {
+        search::rank_plays(position, dice, &SearchConfig::default(), &*self.evaluator())
+    }
+
+    // where a field's number is drawn, 0 and 25 are bar or tray depending on the move
+    fn field_label(field: usize, is_source: bool) -> (u16, u16, String) {
+        match field {
+            1..=12 => (((12 - field) * 5) as u16, 15, field.to_string()),
+            13..=24 => (((field - 13) * 5) as u16, 0, field.to_string()),
+            // bar is drawn as " 25     0" at (65, 1), tray as " 0      25" at (65, 4)
+            _ => {
+                let (x, y) = match (is_source, field) {
+                    (true, 25) => (66, 1),
+                    (true, _) => (72, 1),
+                    (false, 0) => (66, 4),
+                    (false, _) => (73, 4),
+                };
+                (x, y, field.to_string())
+            }
+        }
+    }
+
+    // best plays for the rest of the roll, the best one is highlighted on the board
+    fn show_hint(&self) {
+        let ranked = self.rank_plays(&self.position, &self.roll_result);
+        let Some(best) = ranked.first() else {
+            return;
+        };
+        print_at(60, HINT_LINE, &format!("{:<40}", "Hint (equity):"));
+        for line in 0..HINT_COUNT {
+            let text = ranked.get(line).map_or(String::new(), |ranked_play| {
+                let equity = if line == 0 {
+                    ranked_play.equity
+                } else {
+                    ranked_play.equity - best.equity
+                };
+                let play = notation::format_play(&self.position, &ranked_play.play);
+                format!("{}. {play:<24} {equity:+.3}", line + 1)
+            });
+            print_at(60, HINT_LINE + 1 + line as u16, &format!("{text:<40}"));
+        }
+        for &(source, destination) in &best.play {
+            for (field, is_source) in [(source, true), (destination, false)] {
+                let (x, y, label) = Self::field_label(field, is_source);
+                print_highlighted(x, y, &label);
+            }
+        }
+    }
+
     fn draw_checker(&self, index: usize) {
-        if self.board[index] <= 15 {
+        if self.position.board[index].color == Some(WHITE) {
             print!("●");
         } else {
             print!("○");
@@ -817,16 +1220,16 @@ impl Game {
     fn draw_empty_field(i: usize) {
         for j in 0..3 {
             if i < 12 {
-                move_cursor(((11 - i) * 5) as u16, (15 - (j + BOARD_OFFSET)) as u16);
+                move_cursor(((11 - i) * 5) as u16, 15 - (j + BOARD_OFFSET));
             } else {
-                move_cursor(((i - 12) * 5) as u16, j + BOARD_OFFSET as u16);
+                move_cursor(((i - 12) * 5) as u16, j + BOARD_OFFSET);
             }
             print!("|");
         }
     }
 
     fn clear_board() {
-        for i in 0..15 {
+        for i in 0..LINE_NUMBER_1 {
             clear_line(i);
         }
     }
@@ -843,17 +1246,13 @@ impl Game {
             15,
             "12   11   10   9    8    7    6    5    4    3    2    1",
         );
-        for i in 0..self.board.len() {
-            let mut checker_count = self.board[i] as u16;
+        for i in 0..self.position.board.len() {
+            let checker_count = self.position.board[i].count as u16;
             if checker_count == 0 {
                 Self::draw_empty_field(i);
                 continue;
             }
 
-            if checker_count > 15 {
-                checker_count -= 15;
-            }
-
             let draw_count = checker_count.min(MAX_CHECKERS_DRAWN);
             for j in 0..draw_count {
                 if i < 12 {
@@ -879,17 +1278,70 @@ impl Game {
         move_cursor(60, 0);
         print!(
             "Bar:  {} x ●, {} x ○",
-            self.bar[WHITE as usize], self.bar[BLACK as usize]
+            self.position.bar[WHITE as usize], self.position.bar[BLACK as usize]
         );
         move_cursor(65, 1);
         print!(" 25     0");
         move_cursor(60, 3);
         print!(
             "Tray: {} x ●, {} x ○",
-            self.tray[WHITE as usize], self.tray[BLACK as usize]
+            self.position.tray[WHITE as usize], self.position.tray[BLACK as usize]
         );
         move_cursor(65, 4);
         print!(" 0      25");
+        move_cursor(60, 6);
+        match self.cube_owner {
+            WHITE => print!("Cube: {} (●)", self.cube_value),
+            BLACK => print!("Cube: {} (○)", self.cube_value),
+            _ => print!("Cube: {} (centered)", self.cube_value),
+        }
+        if self.match_length > 0 {
+            move_cursor(60, 8);
+            print!(
+                "Match to {}: ● {} - ○ {}",
+                self.match_length, self.score[WHITE as usize], self.score[BLACK as usize]
+            );
+            move_cursor(60, 9);
+            if self.is_crawford {
+                print!("Crawford game");
+            } else if self.is_post_crawford {
+                print!("Post-Crawford");
+            }
+        }
+        for (color, symbol) in [(WHITE, '●'), (BLACK, '○')] {
+            if let Some(difficulty) = &self.bots[color as usize] {
+                move_cursor(60, 11 + color as u16);
+                print!("{symbol}: computer ({})", difficulty.name());
+            }
+        }
+        self.draw_pip_counts();
+    }
+
+    // exact from the bear-off database when both sides are in it
+    fn effective_pip_count(&self, color: u8) -> f64 {
+        self.bearoff
+            .as_ref()
+            .and_then(|database| database.effective_pip_count(&self.position, color))
+            .unwrap_or_else(|| self.position.effective_pip_count(color))
+    }
+
+    fn draw_pip_counts(&self) {
+        let white = self.position.pip_count(WHITE);
+        let black = self.position.pip_count(BLACK);
+        let lead = match white.cmp(&black) {
+            Ordering::Less => format!("● leads by {}", black - white),
+            Ordering::Greater => format!("○ leads by {}", white - black),
+            Ordering::Equal => "even".to_string(),
+        };
+        let mut message = format!("Pips: ● {white}, ○ {black} ({lead})");
+        if self.position.is_race() {
+            message.push_str(&format!(
+                " | Race, effective: ● {:.1}, ○ {:.1}",
+                self.effective_pip_count(WHITE),
+                self.effective_pip_count(BLACK)
+            ));
+        }
+        print_at(0, PIP_LINE, &message);
     }
 
     fn draw(&self) {
@@ -898,149 +1350,374 @@ impl Game {
         // TODO: menu, other UI components
     }
 
-    fn get_number(&mut self, mode: &str) -> Option<u8> {
+    fn get_number(&mut self, prompt: &str) -> Option<u8> {
         print_message(0, LINE_NUMBER_1, "ESC - reset selection");
-        let prompt = format!("Enter {mode} number:");
-        print_message(0, LINE_NUMBER_3, &prompt);
+        print_message(0, LINE_NUMBER_3, prompt);
 
         let mut input = String::new();
-        while self.is_running {
-            if let Ok(event) = read() {
-                if let Event::Key(key_event) = event {
-                    clear_line(LINE_NUMBER_4);
-                    match key_event.code {
-                        KeyCode::Char(c) if c.is_digit(10) => {
-                            input.push(c);
-                            let input_str = input.to_string();
-                            print_message(0, 20, &input_str);
-                        }
-                        KeyCode::Enter => {
-                            if let Ok(num) = input.parse::<u8>() {
-                                if (0..=25).contains(&num) {
-                                    // temporary for moving from bar
-                                    clear_line(LINE_NUMBER_4);
-                                    return Some(num);
-                                } else {
-                                    print_temp_message(0, LINE_NUMBER_4, "Invalid number", 1000);
-                                }
+        loop {
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                clear_line(LINE_NUMBER_4);
+                match key_event.code {
+                    KeyCode::Char(c) if c.is_ascii_digit() => {
+                        input.push(c);
+                        let input_str = input.to_string();
+                        print_message(0, 20, &input_str);
+                    }
+                    KeyCode::Enter => {
+                        if let Ok(num) = input.parse::<u8>() {
+                            if (0..=25).contains(&num) {
+                                // temporary for moving from bar
+                                clear_line(LINE_NUMBER_4);
+                                return Some(num);
                             } else {
                                 print_temp_message(0, LINE_NUMBER_4, "Invalid number", 1000);
                             }
-                            input.clear();
+                        } else {
+                            print_temp_message(0, LINE_NUMBER_4, "Invalid number", 1000);
                         }
-                        KeyCode::Backspace => {
-                            input.pop();
-                            println!("{}", input);
+                        input.clear();
+                    }
+                    KeyCode::Backspace => {
+                        input.pop();
+                        println!("{}", input);
+                    }
+                    KeyCode::Esc => break,
+                    _ => {}
+                }
+            }
+        }
+        None
+    }
+
+    // moves typed in notation, all or the first few of a legal play for the rest of the roll
+    fn get_play(&mut self) -> Option<Play> {
+        print_message(0, LINE_NUMBER_1, "ESC - reset selection, H)int");
+        print_message(0, LINE_NUMBER_3, "Enter moves, e.g. 24/18 13/11:");
+        let plays: Vec<Play> = self
+            .plays
+            .iter()
+            .filter_map(|play| play.strip_prefix(&self.played[..]))
+            .map(<[Move]>::to_vec)
+            .collect();
+
+        let mut input = String::new();
+        loop {
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                match key_event.code {
+                    KeyCode::Char('h') => self.show_hint(),
+                    KeyCode::Char(c) if c.is_ascii_alphanumeric() || "/*() ".contains(c) => {
+                        input.push(c);
+                    }
+                    KeyCode::Enter => match notation::match_play(&input, &self.position, &plays) {
+                        Ok(play) => {
+                            clear_line(LINE_NUMBER_4);
+                            return Some(play);
                         }
-                        KeyCode::Esc => break,
-                        _ => {}
+                        Err(error) => {
+                            print_temp_message(0, LINE_NUMBER_4, error.message(), 1500);
+                            input.clear();
+                        }
+                    },
+                    KeyCode::Backspace => {
+                        input.pop();
                     }
+                    KeyCode::Esc => break,
+                    _ => {}
                 }
+                print_message(0, LINE_NUMBER_4, &input);
             }
         }
+        clear_line(LINE_NUMBER_4);
         None
     }
 
     fn reset(&mut self) {
-        self.board = [
-            2 + 15,
-            0,
-            0,
-            0,
-            0,
-            5,
-            0,
-            3,
-            0,
-            0,
-            0,
-            5 + 15,
-            5,
-            0,
-            0,
-            0,
-            3 + 15,
-            0,
-            5 + 15,
-            0,
-            0,
-            0,
-            0,
-            2,
-        ];
-        self.turn = WHITE;
+        self.position = Position::new();
         self.roll_result.clear();
         self.moves.clear();
+        self.plays.clear();
+        self.played.clear();
         self.previous_moves.clear();
-        self.bar = [0, 0];
-        self.tray = [0, 0];
+        self.turns.clear();
+        self.cube_actions.clear();
+        self.analysis.clear();
+        self.annotations.clear();
+        self.rolled_dice.clear();
+        self.preset_roll = None;
+        self.cube_value = 1;
+        self.cube_owner = CENTERED;
         self.is_over = false;
         self.is_running = true;
     }
 
-    fn check_is_over(&mut self, loaded: bool) -> bool {
-        if self.tray[self.turn as usize] == 15 {
-            self.is_over = true;
-            let who_won = if self.turn == WHITE { "White" } else { "Black" };
-            let message = format!("{who_won} has won! Enter winner's nick:");
+    fn reset_match(&mut self) {
+        self.match_length = 0;
+        self.score = [0, 0];
+        self.is_crawford = false;
+        self.is_post_crawford = false;
+        self.finished_games.clear();
+    }
+
+    fn update_match_score(&mut self, winner: u8, points: u32) {
+        self.score[winner as usize] += points;
+        if self.is_crawford {
+            self.is_crawford = false;
+            self.is_post_crawford = true;
+        } else if !self.is_post_crawford && self.score[winner as usize] == self.match_length - 1 {
+            self.is_crawford = true;
+        }
+    }
+
+    fn check_is_over(&mut self) -> bool {
+        if self.position.tray[self.position.turn as usize] == 15 {
+            let multiplier = self.position.win_multiplier(self.position.turn);
+            self.finish_game(self.position.turn, multiplier);
+            return true;
+        }
+        false
+    }
+
+    fn finish_game(&mut self, winner: u8, multiplier: u32) {
+        self.is_over = true;
+        let (score, length) = (self.score, self.match_length);
+        let who_won = if winner == WHITE { "White" } else { "Black" };
+        let kind = match multiplier {
+            1 => "a single game",
+            2 => "a gammon",
+            _ => "a backgammon",
+        };
+        let points = multiplier * self.cube_value;
+        if let Some(replay) = self.replay() {
+            self.finished_games.push(MatGame { score, replay });
+        }
+        let is_match_over = length == 0 || score[winner as usize] + points >= length;
+        let games = if is_match_over {
+            std::mem::take(&mut self.finished_games)
+        } else {
+            Vec::new()
+        };
+        if self.match_length == 0 {
+            let message = format!("{who_won} wins {kind} ({points} points)! Enter winner's nick:");
             print_message(0, LINE_NUMBER_3, &message);
-            let _ = Self::update_leaderboard();
-            if !loaded {
-                print_message(
-                    0,
-                    LINE_NUMBER_3,
-                    "Do you want to save gameplay to replays folder? y/n",
+            let _ = Self::update_leaderboard(points);
+        } else if self.score[winner as usize] + points >= self.match_length {
+            // leaderboard only counts won matches, worth the match length
+            self.score[winner as usize] += points;
+            let message = format!(
+                "{who_won} wins the match {}-{}! Enter winner's nick:",
+                self.score[winner as usize],
+                self.score[opponent(winner) as usize]
+            );
+            print_message(0, LINE_NUMBER_3, &message);
+            let _ = Self::update_leaderboard(self.match_length);
+            self.reset_match();
+        } else {
+            self.update_match_score(winner, points);
+            let message = format!("{who_won} wins {kind} ({points} points)");
+            print_temp_message(0, LINE_NUMBER_3, &message, 1500);
+        }
+        if !self.turns.is_empty() && self.ask_yes_no("Do you want to analyze the game? y/n") {
+            self.analyze();
+            self.print_analysis_summary(0, LINE_NUMBER_4);
+            print_message(0, LINE_NUMBER_3, "Press any key to continue");
+            let _ = read();
+            for line in LINE_NUMBER_4..=LINE_NUMBER_6 {
+                clear_line(line);
+            }
+        }
+        if self.is_recording()
+            && self.ask_yes_no("Do you want to save gameplay to replays folder? y/n")
+        {
+            let _ = self.save_previous_moves();
+        }
+        let kind = if length == 0 { "game" } else { "match" };
+        let question = format!("Export the {kind}? M) .mat file, G) .sgf file, N)o");
+        if self.is_recording()
+            && !games.is_empty()
+            && let Some(extension) = self.ask_export_format(&question)
+        {
+            let games = games.into_iter().map(SgfGame::from).collect();
+            self.export_games(length, games, extension);
+        }
+        self.reset();
+    }
+
+    fn ask_yes_no(&self, question: &str) -> bool {
+        print_message(0, LINE_NUMBER_3, question);
+        clear_line(LINE_NUMBER_4);
+        loop {
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                match key_event.code {
+                    KeyCode::Char('y') => return true,
+                    KeyCode::Char('n') => return false,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn ask_export_format(&self, question: &str) -> Option<&'static str> {
+        print_message(0, LINE_NUMBER_3, question);
+        clear_line(LINE_NUMBER_4);
+        loop {
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                match key_event.code {
+                    KeyCode::Char('m') => return Some(MAT_EXTENSION),
+                    KeyCode::Char('g') => return Some(SGF_EXTENSION),
+                    KeyCode::Char('n') => return None,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn can_double(&self) -> bool {
+        (self.cube_owner == CENTERED || self.cube_owner == self.position.turn)
+            && self.cube_value < MAX_CUBE_VALUE
+            && !self.is_crawford
+    }
+
+    // returns true if the double was passed and the game is over
+    fn offer_double(&mut self) -> bool {
+        let (doubler, taker) = if self.position.turn == WHITE {
+            ("White", "Black")
+        } else {
+            ("Black", "White")
+        };
+        let message = format!(
+            "{doubler} doubles to {}. {taker}: T)ake, P)ass, H)int",
+            self.cube_value * 2
+        );
+        self.record_cube_action(self.position.turn, Action::Double);
+        print_message(0, LINE_NUMBER_3, &message);
+        loop {
+            let code = if let Some(difficulty) = &self.bots[opponent(self.position.turn) as usize] {
+                thread::sleep(BOT_DELAY);
+                let analysis = bot::cube_analysis(
+                    &self.position,
+                    &self.cube_state(),
+                    difficulty,
+                    self.bearoff.as_ref(),
                 );
-                clear_line(LINE_NUMBER_4);
-                loop {
-                    if let Ok(event) = read() {
-                        if let Event::Key(key_event) = event {
-                            match key_event.code {
-                                KeyCode::Char('y') => {
-                                    let _ = self.save_previous_moves();
-                                    break;
-                                }
-                                KeyCode::Char('n') => break,
-                                _ => {}
-                            }
-                        }
+                if analysis.is_none_or(|analysis| analysis.should_take()) {
+                    KeyCode::Char('t')
+                } else {
+                    KeyCode::Char('p')
+                }
+            } else if let Ok(Event::Key(key_event)) = read() {
+                key_event.code
+            } else {
+                continue;
+            };
+            match code {
+                KeyCode::Char('t') => {
+                    self.record_cube_action(opponent(self.position.turn), Action::Take);
+                    self.cube_value *= 2;
+                    self.cube_owner = opponent(self.position.turn);
+                    if self.is_recording() {
+                        self.record_move();
                     }
+                    clear_line(LINE_NUMBER_3);
+                    return false;
+                }
+                KeyCode::Char('p') => {
+                    let message = format!("{taker} passes");
+                    print_temp_message(0, LINE_NUMBER_3, &message, 1000);
+                    self.record_cube_action(opponent(self.position.turn), Action::Pass);
+                    // a passed double always counts as a single game
+                    self.finish_game(self.position.turn, 1);
+                    return true;
                 }
+                KeyCode::Char('h') => self.show_cube_hint(),
+                _ => {}
             }
-            self.reset();
-            return true;
         }
-        false
+    }
+
+    // cube seen from the side on turn
+    fn cube_state(&self) -> CubeState {
+        let turn = self.position.turn;
+        let owner = match self.cube_owner {
+            CENTERED => Ownership::Centered,
+            owner if owner == turn => Ownership::Mine,
+            _ => Ownership::Theirs,
+        };
+        let away = (self.match_length > 0).then(|| {
+            [
+                self.match_length - self.score[turn as usize],
+                self.match_length - self.score[opponent(turn) as usize],
+            ]
+        });
+        CubeState {
+            value: self.cube_value,
+            owner,
+            away,
+            is_post_crawford: self.is_post_crawford,
+        }
+    }
+
+    // proper cube action for the side on turn, also tells the opponent whether to take
+    fn show_cube_hint(&self) {
+        let analysis =
+            cube::analyze_position(&self.position, &self.cube_state(), &*self.evaluator());
+        let lines = [
+            format!("Cube: {}", analysis.decision.name()),
+            format!("No double     {:+.3}", analysis.no_double),
+            format!("Double, take  {:+.3}", analysis.double_take),
+            format!("Double, pass  {:+.3}", analysis.double_pass),
+        ];
+        for (line, text) in lines.iter().enumerate() {
+            print_at(60, HINT_LINE + line as u16, &format!("{text:<40}"));
+        }
     }
 
     fn quit(&mut self) {
         self.is_running = false;
     }
 
-    fn choose_who_starts(&mut self) {
+    // returns false if the game shouldn't start
+    fn choose_who_starts(&mut self) -> bool {
         let mut rolls_count = 0;
         while rolls_count < 2 {
             self.draw();
             self.print_turn();
             print_message(0, LINE_NUMBER_1, "R)oll, Q)uit");
-            if let Ok(event) = read() {
-                if let Event::Key(key_event) = event {
-                    match key_event.code {
-                        KeyCode::Char('r') => {
-                            rolls_count += 1;
-                            let dice = Self::roll();
-                            self.roll_result.push(dice);
-                            let dice_str = format!("Result: {dice}");
-                            print_temp_message(0, LINE_NUMBER_5, &dice_str, 1000);
-                            self.change_turn();
-                        }
-                        KeyCode::Char('q') => {
-                            self.quit();
-                            return;
-                        }
-                        _ => {}
-                    }
+            // computer players roll on their own
+            let code = if self.bots[self.position.turn as usize].is_some() {
+                thread::sleep(BOT_DELAY);
+                KeyCode::Char('r')
+            } else if let Ok(Event::Key(key_event)) = read() {
+                key_event.code
+            } else {
+                continue;
+            };
+            match code {
+                KeyCode::Char('r') => {
+                    let Some(dice) = self.dice.roll_die() else {
+                        print_temp_message(0, LINE_NUMBER_4, "No dice rolled", 1000);
+                        return false;
+                    };
+                    rolls_count += 1;
+                    self.roll_result.push(dice);
+                    self.rolled_dice.push(dice);
+                    let dice_str = format!("Result: {dice}");
+                    print_temp_message(0, LINE_NUMBER_5, &dice_str, 1000);
+                    self.position.change_turn();
+                }
+                KeyCode::Char('q') => {
+                    self.quit();
+                    return false;
                 }
+                _ => {}
             }
             if rolls_count == 2 && self.roll_result[0] == self.roll_result[1] {
                 self.roll_result.clear();
@@ -1049,97 +1726,564 @@ impl Game {
             }
         }
         if self.roll_result[0] > self.roll_result[1] {
-            self.turn = WHITE;
+            self.position.turn = WHITE;
             print_temp_message(0, LINE_NUMBER_3, "White starts", 1000);
         } else {
-            self.turn = BLACK;
+            self.position.turn = BLACK;
             print_temp_message(0, LINE_NUMBER_3, "Black starts", 1000);
         }
+        true
     }
 
     fn play(&mut self, loaded: bool) {
         if !loaded {
-            self.choose_who_starts();
+            if self.is_fair {
+                self.setup_fair_dice();
+            }
+            self.start_seed = self.dice.seed();
+            if !self.choose_who_starts() {
+                self.reset();
+                self.reset_match();
+                return;
+            }
             self.record_move();
         }
         while self.is_running {
             self.draw();
-            print_message(
-                0,
-                LINE_NUMBER_1,
-                "R)oll, S)ave, Q)uit, M)enu (without save)",
-            );
+            if let Some(difficulty) = self.bots[self.position.turn as usize].clone() {
+                if self.play_bot_turn(difficulty) {
+                    return;
+                }
+                continue;
+            }
+            if self.can_double() {
+                print_message(
+                    0,
+                    LINE_NUMBER_1,
+                    "R)oll, D)ouble, H)int, I)D, S)ave, Q)uit, M)enu (without save)",
+                );
+            } else {
+                print_message(
+                    0,
+                    LINE_NUMBER_1,
+                    "R)oll, I)D, S)ave, Q)uit, M)enu (without save)",
+                );
+            }
             self.print_turn();
-            if let Ok(event) = read() {
-                if let Event::Key(key_event) = event {
-                    match key_event.code {
-                        KeyCode::Char('r') => {
-                            self.handle_roll();
-                            while !self.roll_result.is_empty() {
-                                self.draw_board();
-                                self.print_turn();
-                                self.generate_moves();
-                                if self.moves.is_empty() {
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                match key_event.code {
+                    KeyCode::Char('r') if self.handle_roll() => {
+                        self.plays = self.position.generate_plays(&self.roll_result);
+                        self.played.clear();
+                        loop {
+                            self.draw_board();
+                            self.print_turn();
+                            self.moves = legal_moves(&self.plays, &self.played);
+                            if self.moves.is_empty() {
+                                if self.played.is_empty() {
                                     print_temp_message(0, LINE_NUMBER_4, "No moves possible", 1000);
-                                    break;
                                 }
-                                self.print_moves();
-                                if let Some(source) = self.get_number("source") {
-                                    if let Some(destination) = self.get_number("destination") {
-                                        if self
-                                            .moves
-                                            .contains(&(source as usize, destination as usize))
-                                        {
-                                            self.move_checker(
-                                                source as usize,
-                                                destination as usize,
-                                            );
-                                            if !loaded {
-                                                self.record_move();
-                                            }
-                                        } else {
-                                            print_temp_message(
-                                                0,
-                                                LINE_NUMBER_4,
-                                                "Invalid move",
-                                                1000,
-                                            );
-                                        }
-                                    }
-                                }
-                                if self.check_is_over(loaded) {
-                                    return;
+                                break;
+                            }
+                            self.print_moves();
+                            for mv in self.get_play().unwrap_or_default() {
+                                self.position
+                                    .move_checker(mv.0, mv.1, &mut self.roll_result);
+                                self.played.push(mv);
+                                self.record_turn_move(mv);
+                                if self.is_recording() {
+                                    self.record_move();
                                 }
                             }
-                            self.change_turn();
+                            if self.check_is_over() {
+                                return;
+                            }
                         }
-                        KeyCode::Char('s') => {
-                            if let Ok(()) = self.save_to_file() {
-                                print_temp_message(
-                                    0,
-                                    LINE_NUMBER_4,
-                                    "Saved game state successfully",
-                                    1000,
+                        self.position.change_turn();
+                    }
+                    KeyCode::Char('d') if self.can_double() => {
+                        let passed = self.offer_double();
+                        if passed {
+                            return;
+                        }
+                    }
+                    KeyCode::Char('h') if self.can_double() => {
+                        self.show_cube_hint();
+                        let _ = read();
+                    }
+                    // the game goes on from a pasted position
+                    KeyCode::Char('i') if self.exchange_ids() && self.is_recording() => {
+                        self.restart_recording()
+                    }
+                    KeyCode::Char('s') => {
+                        if let Ok(()) = self.save_to_file() {
+                            print_temp_message(
+                                0,
+                                LINE_NUMBER_4,
+                                "Saved game state successfully",
+                                1000,
+                            );
+                        } else {
+                            print_temp_message(
+                                0,
+                                LINE_NUMBER_4,
+                                "Saved game state successfully",
+                                1000,
+                            );
+                        }
+                    }
+                    KeyCode::Char('m') => {
+                        self.reset();
+                        self.reset_match();
+                        return;
+                    }
+                    KeyCode::Char('q') => self.quit(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // returns true if the game ended or the player left during the computer's turn
+    fn play_bot_turn(&mut self, difficulty: Difficulty) -> bool {
+        self.print_turn();
+        print_message(
+            0,
+            LINE_NUMBER_1,
+            "Computer's turn, M)enu (without save), Q)uit",
+        );
+        if let Ok(true) = poll(BOT_DELAY)
+            && let Ok(Event::Key(key_event)) = read()
+        {
+            match key_event.code {
+                KeyCode::Char('m') => {
+                    self.reset();
+                    self.reset_match();
+                    return true;
+                }
+                KeyCode::Char('q') => {
+                    self.quit();
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        if self.can_double()
+            && let Some(analysis) = bot::cube_analysis(
+                &self.position,
+                &self.cube_state(),
+                &difficulty,
+                self.bearoff.as_ref(),
+            )
+            && analysis.should_double()
+            && self.offer_double()
+        {
+            return true;
+        }
+        if !self.handle_roll() {
+            return false;
+        }
+
+        let play = bot::choose_play(
+            &self.position,
+            &self.roll_result,
+            &difficulty,
+            self.bearoff.as_ref(),
+        );
+        if play.is_empty() {
+            print_temp_message(0, LINE_NUMBER_4, "No moves possible", 1000);
+        }
+        for (source, destination) in play {
+            let text = notation::format_play(&self.position, &[(source, destination)]);
+            self.position
+                .move_checker(source, destination, &mut self.roll_result);
+            self.record_turn_move((source, destination));
+            if self.is_recording() {
+                self.record_move();
+            }
+            self.draw_board();
+            let message = format!("Computer moves {text}");
+            print_temp_message(0, LINE_NUMBER_6, &message, BOT_DELAY.as_millis() as u64);
+        }
+        if self.check_is_over() {
+            return true;
+        }
+        self.position.change_turn();
+        false
+    }
+
+    fn choose_bots(&mut self) {
+        for (color, name) in [(WHITE, "White"), (BLACK, "Black")] {
+            self.draw();
+            let message = format!(
+                "{name}: H)uman, computer: R)andom, G)reedy, L)ookahead, E)xpert, N)eural, ESC - back"
+            );
+            print_message(0, LINE_NUMBER_1, &message);
+            loop {
+                if let Ok(Event::Key(key_event)) = read() {
+                    self.bots[color as usize] = match key_event.code {
+                        KeyCode::Char('h') => None,
+                        KeyCode::Char('r') => Some(Difficulty::Random),
+                        KeyCode::Char('g') => Some(Difficulty::Greedy),
+                        KeyCode::Char('l') => Some(Difficulty::Lookahead),
+                        KeyCode::Char('e') => {
+                            let depth = self.get_number("Enter search depth (1-3 plies):");
+                            Some(Difficulty::Expert(depth.unwrap_or(2).clamp(1, 3) as u32))
+                        }
+                        KeyCode::Char('n') => match Network::load(network::DEFAULT_PATH) {
+                            Ok(network) => {
+                                let depth = self.get_number("Enter search depth (1-3 plies):");
+                                Some(Difficulty::Neural {
+                                    network: Arc::new(network),
+                                    depth: depth.unwrap_or(1).clamp(1, 3) as u32,
+                                })
+                            }
+                            Err(_) => {
+                                let message = format!(
+                                    "No network in {}, train one with: cargo run --release --bin train",
+                                    network::DEFAULT_PATH
                                 );
-                            } else {
-                                print_temp_message(
+                                print_temp_message(0, LINE_NUMBER_4, &message, 2000);
+                                continue;
+                            }
+                        },
+                        KeyCode::Esc => return,
+                        _ => continue,
+                    };
+                    break;
+                }
+            }
+        }
+    }
+
+    // Each player commits to a secret, typed without showing it so the other player can't
+    // pick theirs to match. Players who typed their own secret are shown their nonce as a key,
+    // going on from a save needs both. The hashes are shown before the game starts.
+    fn setup_fair_dice(&mut self) {
+        self.draw();
+        let mut secrets: [String; 2] = Default::default();
+        let mut typed = [false; 2];
+        for (color, player) in ["White", "Black"].into_iter().enumerate() {
+            let prompt = format!(
+                "{player}: type a secret word (needed to go on from a save) or press Enter for a random one"
+            );
+            print_message(0, LINE_NUMBER_3, &prompt);
+            let secret = Self::get_hidden_line();
+            typed[color] = fair_dice::is_valid_secret(&secret);
+            secrets[color] = if typed[color] {
+                secret
+            } else {
+                fair_dice::random_secret()
+            };
+        }
+        let dice = FairDice::new(secrets);
+        let opponents = ["Black", "White"];
+        for (color, player) in ["White", "Black"].into_iter().enumerate() {
+            if !typed[color] {
+                continue;
+            }
+            let message = format!(
+                "{player}, press anything to see your key ({} looks away)",
+                opponents[color]
+            );
+            print_message(0, LINE_NUMBER_3, &message);
+            let _ = read();
+            let message = format!(
+                "{player}'s key, note it with your secret: {}",
+                dice.nonces()[color]
+            );
+            print_message(0, LINE_NUMBER_3, &message);
+            print_message(0, LINE_NUMBER_4, "Press anything to hide it");
+            let _ = read();
+            clear_line(LINE_NUMBER_4);
+        }
+        clear_line(LINE_NUMBER_3);
+        self.dice = Box::new(dice);
+
+        if let Some(proof) = self.dice.fair_proof() {
+            let [white, black] = &proof.commitments;
+            print_message(
+                0,
+                LINE_NUMBER_2,
+                "Commitments (secrets are revealed in the replay):",
+            );
+            print_message(0, LINE_NUMBER_3, &format!("White: {white}"));
+            print_message(0, LINE_NUMBER_4, &format!("Black: {black}"));
+            print_message(0, LINE_NUMBER_6, "Press anything to start");
+            let _ = read();
+            for line in [LINE_NUMBER_2, LINE_NUMBER_3, LINE_NUMBER_4, LINE_NUMBER_6] {
+                clear_line(line);
+            }
+        }
+    }
+
+    // a saved game's fair dice go on once both players typed their secrets and keys again,
+    // returns false when one doesn't match its commitment and the game can't go on
+    fn resume_fair_dice(&mut self, commitments: [&str; 2], rolled: u64) -> bool {
+        let mut secrets: [String; 2] = Default::default();
+        let mut nonces: [String; 2] = Default::default();
+        for (color, player) in ["White", "Black"].into_iter().enumerate() {
+            let prompt = format!("{player}: type your secret to go on with fair dice");
+            print_message(0, LINE_NUMBER_3, &prompt);
+            secrets[color] = Self::get_hidden_line();
+            print_message(0, LINE_NUMBER_3, &format!("{player}: type your key"));
+            nonces[color] = Self::get_hidden_line();
+            if nonces[color].is_empty()
+                || fair_dice::commitment(&secrets[color], &nonces[color]) != commitments[color]
+            {
+                let message = "Wrong secret or key, the saved game can't go on";
+                print_temp_message(0, LINE_NUMBER_3, message, 1500);
+                return false;
+            }
+        }
+        clear_line(LINE_NUMBER_3);
+        self.dice = Box::new(FairDice::resume(secrets, nonces, rolled));
+        self.is_fair = true;
+        true
+    }
+
+    // a dice source picked in the menu, fair dice are only set up when a game starts
+    fn set_dice(&mut self, dice: Box<dyn DiceSource>) {
+        self.dice = dice;
+        self.is_fair = false;
+    }
+
+    fn choose_dice(&mut self) {
+        self.draw();
+        print_message(
+            0,
+            LINE_NUMBER_1,
+            "Dice: R)andom, S)eeded, M)anual entry, F)ile script (saves/dice), P)rovably fair, ESC - back",
+        );
+        loop {
+            if let Ok(Event::Key(key_event)) = read() {
+                match key_event.code {
+                    KeyCode::Char('r') => self.set_dice(Box::new(SeededDice::random())),
+                    KeyCode::Char('s') => {
+                        print_message(0, LINE_NUMBER_3, "Enter seed:");
+                        match Self::get_line().map(|line| line.parse::<u64>()) {
+                            Ok(Ok(seed)) => self.set_dice(Box::new(SeededDice::new(seed))),
+                            _ => print_temp_message(0, LINE_NUMBER_4, "Invalid seed", 1000),
+                        }
+                    }
+                    KeyCode::Char('m') => self.set_dice(Box::new(ManualDice)),
+                    KeyCode::Char('p') => self.is_fair = true,
+                    KeyCode::Char('f') => {
+                        if let Ok(Some(filename)) = self.get_filename("saves/dice") {
+                            match ScriptedDice::from_file(&format!("saves/dice/{filename}")) {
+                                Ok(dice) => self.set_dice(Box::new(dice)),
+                                Err(_) => print_temp_message(
                                     0,
                                     LINE_NUMBER_4,
-                                    "Saved game state successfully",
+                                    "Invalid dice script",
                                     1000,
-                                );
+                                ),
                             }
                         }
-                        KeyCode::Char('m') => {
-                            self.reset();
-                            return;
+                    }
+                    KeyCode::Esc => {}
+                    _ => continue,
+                }
+                return;
+            }
+        }
+    }
+
+    fn match_id(&self) -> MatchId {
+        MatchId {
+            cube_value: self.cube_value,
+            cube_owner: (self.cube_owner != CENTERED).then_some(self.cube_owner),
+            on_roll: self.position.turn,
+            is_crawford: self.is_crawford,
+            dice: self.preset_roll,
+            match_length: self.match_length,
+            score: self.score,
+        }
+    }
+
+    fn apply_match_id(&mut self, match_id: &MatchId) {
+        self.cube_value = match_id.cube_value;
+        self.cube_owner = match_id.cube_owner.unwrap_or(CENTERED);
+        self.position.turn = match_id.on_roll;
+        self.preset_roll = match_id.dice;
+        self.match_length = match_id.match_length;
+        self.score = match_id.score;
+        self.is_crawford = match_id.is_crawford;
+        // someone one point away outside the Crawford game means it was played already
+        self.is_post_crawford = !match_id.is_crawford
+            && match_id.match_length > 0
+            && match_id
+                .score
+                .iter()
+                .any(|&score| score + 1 == match_id.match_length);
+    }
+
+    // shows the gnubg ID and XGID of the current state, a pasted one replaces it. Returns
+    // true when it did
+    fn exchange_ids(&mut self) -> bool {
+        let match_id = self.match_id();
+        let gnubg_id = format!(
+            "{}:{}",
+            gnubg::position_id(&self.position),
+            match_id.encode()
+        );
+        print_message(0, LINE_NUMBER_5, &format!("GNU ID: {gnubg_id}"));
+        print_message(0, LINE_NUMBER_6, &xgid::encode(&self.position, &match_id));
+        print_message(
+            0,
+            LINE_NUMBER_3,
+            "Paste a GNU ID (Position ID or Position ID:Match ID) or XGID, Enter to keep:",
+        );
+        let Ok(text) = Self::get_line() else {
+            return false;
+        };
+        if text.trim().is_empty() {
+            return false;
+        }
+        let parsed = xgid::parse(&text)
+            .map(|(position, match_id)| (position, Some(match_id)))
+            .or_else(|| gnubg::parse_gnubg_id(&text, self.position.turn));
+        match parsed {
+            Some((position, _)) if position.is_over() => {
+                print_temp_message(0, LINE_NUMBER_4, "The game is already over", 1000);
+                false
+            }
+            Some((position, match_id)) => {
+                self.position = position;
+                if let Some(match_id) = match_id {
+                    self.apply_match_id(&match_id);
+                }
+                true
+            }
+            None => {
+                print_temp_message(0, LINE_NUMBER_4, "Invalid ID", 1000);
+                false
+            }
+        }
+    }
+
+    // both sides need all 15 checkers and the game must not be over yet
+    fn check_setup(&self) -> bool {
+        for (color, name) in [(WHITE, "White"), (BLACK, "Black")] {
+            let total = self.position.checker_total(color);
+            if total != 15 {
+                let message = format!("{name} has {total} checkers, needs 15");
+                print_temp_message(0, LINE_NUMBER_4, &message, 1500);
+                return false;
+            }
+        }
+        if self.position.is_over() {
+            print_temp_message(0, LINE_NUMBER_4, "The game is already over", 1500);
+            return false;
+        }
+        true
+    }
+
+    fn edit_position(&mut self) {
+        self.reset();
+        self.reset_match();
+        let mut color = WHITE;
+        while self.is_running {
+            self.draw();
+            print_message(
+                0,
+                LINE_NUMBER_1,
+                "W)hite/B)lack, A)dd, R)emove, C)lear, N)ew, T)urn, D)ice, I)D, S)ave, P)lay, ESC - back",
+            );
+            let symbol = |color| if color == WHITE { '●' } else { '○' };
+            let dice = self
+                .preset_roll
+                .map_or("roll".to_string(), |(dice_1, dice_2)| {
+                    format!("{dice_1}-{dice_2}")
+                });
+            let message = format!(
+                "Placing {}, {} to move, dice: {dice}, checkers: ● {}/15, ○ {}/15",
+                symbol(color),
+                symbol(self.position.turn),
+                self.position.checker_total(WHITE),
+                self.position.checker_total(BLACK)
+            );
+            print_message(0, LINE_NUMBER_2, &message);
+            let Ok(Event::Key(key_event)) = read() else {
+                continue;
+            };
+            match key_event.code {
+                KeyCode::Char('w') => color = WHITE,
+                KeyCode::Char('b') => color = BLACK,
+                KeyCode::Char(key @ ('a' | 'r')) => {
+                    let prompt = "Enter field (0-25, bar and tray as shown next to the board):";
+                    if let Some(field) = self.get_number(prompt) {
+                        let done = if key == 'a' {
+                            self.position.place_checker(field as usize, color)
+                        } else {
+                            self.position.remove_checker(field as usize, color)
+                        };
+                        if !done {
+                            print_temp_message(0, LINE_NUMBER_4, "Not possible there", 1000);
                         }
-                        KeyCode::Char('q') => self.quit(),
-                        _ => {}
                     }
                 }
+                KeyCode::Char('c') => {
+                    self.position = Position::from_checkers(&[], self.position.turn, [0, 0], [0, 0])
+                }
+                KeyCode::Char('n') => self.position = Position::new(),
+                KeyCode::Char('t') => self.position.change_turn(),
+                KeyCode::Char('i') => {
+                    self.exchange_ids();
+                }
+                KeyCode::Char('d') if self.is_fair => {
+                    let message = "Fair dice are always rolled";
+                    print_temp_message(0, LINE_NUMBER_4, message, 1000);
+                }
+                KeyCode::Char('d') => {
+                    // ESC clears the dice, the game then starts with a roll
+                    self.preset_roll = match (ManualDice.roll_die(), ManualDice.roll_die()) {
+                        (Some(dice_1), Some(dice_2)) => Some((dice_1, dice_2)),
+                        _ => None,
+                    };
+                }
+                KeyCode::Char('s') if self.check_setup() => {
+                    if self.save_to_file().is_ok() {
+                        print_temp_message(0, LINE_NUMBER_4, "Saved position", 1000);
+                    } else {
+                        print_temp_message(0, LINE_NUMBER_4, "Saving failed", 1000);
+                    }
+                }
+                KeyCode::Char('p') if self.check_setup() => {
+                    if self.is_fair {
+                        self.setup_fair_dice();
+                    }
+                    self.restart_recording();
+                    self.play_match(true);
+                    return;
+                }
+                KeyCode::Esc => break,
+                KeyCode::Char('q') => self.quit(),
+                _ => {}
             }
         }
+        self.reset();
+    }
+
+    // keeps starting new games until the match is decided, single games return right away
+    fn play_match(&mut self, loaded: bool) {
+        self.play(loaded);
+        while self.match_length > 0 && self.is_running {
+            self.play(false);
+        }
+    }
+
+    fn start_match(&mut self) {
+        self.draw();
+        if let Some(length) = self.get_number("Enter match length:")
+            && length > 0
+        {
+            self.reset_match();
+            self.match_length = length as u32;
+            self.play_match(false);
+        }
     }
 
     pub fn run(&mut self) {
@@ -1148,26 +2292,31 @@ impl Game {
             print_message(
                 0,
                 LINE_NUMBER_1,
-                "P)lay, L)oad, S)how leaderboard, R)eplay, Q)uit",
+                "P)lay, M)atch, L)oad, E)dit, D)ice, B)ots, S)how leaderboard, R)eplay, Q)uit",
             );
-            if let Ok(event) = read() {
-                if let Event::Key(key_event) = event {
-                    match key_event.code {
-                        KeyCode::Char('p') => self.play(false),
-                        KeyCode::Char('l') => {
-                            if self.read_from_file().is_ok() {
-                                self.play(true);
-                            }
-                        }
-                        KeyCode::Char('s') => {
-                            let _ = self.get_leaderboard();
-                        }
-                        KeyCode::Char('r') => {
-                            let _ = self.visualize_replay();
-                        }
-                        KeyCode::Char('q') => self.quit(),
-                        _ => {}
+            if let Ok(event) = read()
+                && let Event::Key(key_event) = event
+            {
+                match key_event.code {
+                    KeyCode::Char('p') => {
+                        self.reset_match();
+                        self.play(false);
+                    }
+                    KeyCode::Char('m') => self.start_match(),
+                    KeyCode::Char('d') => self.choose_dice(),
+                    KeyCode::Char('b') => self.choose_bots(),
+                    KeyCode::Char('l') if self.read_from_file().is_ok() => {
+                        self.play_match(true);
+                    }
+                    KeyCode::Char('e') => self.edit_position(),
+                    KeyCode::Char('s') => {
+                        let _ = self.get_leaderboard();
                     }
+                    KeyCode::Char('r') => {
+                        self.visualize_replay();
+                    }
+                    KeyCode::Char('q') => self.quit(),
+                    _ => {}
                 }
             }
         }
@@ -0,0 +1,310 @@
+use crate::position::{Position, WHITE, opponent};
+
+// GNU Backgammon Position ID and Match ID. Both are bit strings, least significant bit of
+// each byte first, written in base64 without padding.
+//
+// Position ID: for the side not on roll, then the side on roll, every point from its own
+// 1 point up to its 24 point and then its bar, as many 1 bits as checkers followed by a 0.
+// Borne off checkers are whatever is missing from 15.
+//
+// Match ID: cube as a power of two (4 bits), cube owner (2, 3 when centered), side on roll,
+// Crawford game, game state (3), side to act, double offered, resignation (2), both dice
+// (3 each), match length (15) and both scores (15 each). gnubg's player 0 is white.
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const POSITION_BYTES: usize = 10;
+const MATCH_BYTES: usize = 9;
+const CENTERED: u32 = 3;
+const PLAYING: u32 = 1;
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let value = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for index in 0..chunk.len() + 1 {
+            let digit = (value >> (18 - 6 * index)) & 63;
+            text.push(BASE64[digit as usize] as char);
+        }
+    }
+    text
+}
+
+// exactly `length` bytes, padding is allowed but not needed
+fn decode_base64(text: &str, length: usize) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut value = 0u32;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let digit = BASE64.iter().position(|&d| d == c)? as u32;
+        value = (value << 6) | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((value >> bits) as u8);
+            value &= (1 << bits) - 1;
+        }
+    }
+    (bytes.len() == length).then_some(bytes)
+}
+
+fn bit(bytes: &[u8], index: usize) -> bool {
+    bytes[index / 8] & (1 << (index % 8)) != 0
+}
+
+fn get_bits(bytes: &[u8], start: usize, count: usize) -> u32 {
+    (0..count)
+        .filter(|offset| bit(bytes, start + offset))
+        .map(|offset| 1 << offset)
+        .sum()
+}
+
+fn set_bits(bytes: &mut [u8], start: usize, count: usize, value: u32) {
+    for offset in 0..count {
+        if value & (1 << offset) != 0 {
+            let index = start + offset;
+            bytes[index / 8] |= 1 << (index % 8);
+        }
+    }
+}
+
+// field of `color`'s own point counted from its home board
+fn field(color: u8, point: usize) -> usize {
+    if color == WHITE { point } else { 25 - point }
+}
+
+pub fn position_id(position: &Position) -> String {
+    let mut key = [0u8; POSITION_BYTES];
+    let mut index = 0;
+    for color in [opponent(position.turn), position.turn] {
+        let counts = (1..=24)
+            .map(|point| {
+                let field = field(color, point);
+                if position.which_color(field) == Some(color) {
+                    position.checker_count(field)
+                } else {
+                    0
+                }
+            })
+            .chain([position.bar[color as usize]]);
+        for count in counts {
+            set_bits(&mut key, index, count as usize, u32::MAX);
+            index += count as usize + 1;
+        }
+    }
+    encode_base64(&key)
+}
+
+// the ID doesn't say who is on roll, that comes from the Match ID
+pub fn parse_position_id(id: &str, turn: u8) -> Option<Position> {
+    let key = decode_base64(id.trim(), POSITION_BYTES)?;
+    let mut position = Position::from_checkers(&[], turn, [0, 0], [0, 0]);
+    let mut index = 0;
+    for color in [opponent(turn), turn] {
+        for point in 1..=25 {
+            let mut count = 0;
+            while index < POSITION_BYTES * 8 && bit(&key, index) {
+                count += 1;
+                index += 1;
+            }
+            index += 1;
+            let field = if point == 25 {
+                // the bar, as the editor numbers it
+                if color == WHITE { 25 } else { 0 }
+            } else {
+                field(color, point)
+            };
+            for _ in 0..count {
+                if !position.place_checker(field, color) {
+                    return None;
+                }
+            }
+        }
+        if index > POSITION_BYTES * 8 {
+            return None;
+        }
+        position.tray[color as usize] = 15 - position.checker_total(color) as u8;
+    }
+    Some(position)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchId {
+    pub cube_value: u32,
+    pub cube_owner: Option<u8>, // None when centered
+    pub on_roll: u8,
+    pub is_crawford: bool,
+    pub dice: Option<(u8, u8)>, // None before rolling
+    pub match_length: u32,      // 0 for money games
+    pub score: [u32; 2],
+}
+
+// a money game has no score, in a match both scores are below the length
+pub fn is_valid_score(match_length: u32, score: [u32; 2]) -> bool {
+    if match_length == 0 {
+        score == [0, 0]
+    } else {
+        score.iter().all(|&points| points < match_length)
+    }
+}
+
+impl MatchId {
+    pub fn encode(&self) -> String {
+        let mut key = [0u8; MATCH_BYTES];
+        let (dice_1, dice_2) = self.dice.unwrap_or((0, 0));
+        let fields = [
+            (4, self.cube_value.max(1).trailing_zeros()),
+            (2, self.cube_owner.map_or(CENTERED, u32::from)),
+            (1, self.on_roll as u32),
+            (1, self.is_crawford as u32),
+            (3, PLAYING),
+            (1, self.on_roll as u32),
+            (1, 0), // double offered
+            (2, 0), // resignation
+            (3, dice_1 as u32),
+            (3, dice_2 as u32),
+            (15, self.match_length),
+            (15, self.score[0]),
+            (15, self.score[1]),
+        ];
+        let mut start = 0;
+        for (count, value) in fields {
+            set_bits(&mut key, start, count, value);
+            start += count;
+        }
+        encode_base64(&key)
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        let key = decode_base64(id.trim(), MATCH_BYTES)?;
+        let cube_value = 1 << get_bits(&key, 0, 4);
+        let cube_owner = match get_bits(&key, 4, 2) {
+            CENTERED => None,
+            owner @ 0..=1 => Some(owner as u8),
+            _ => return None,
+        };
+        let dice = match (get_bits(&key, 15, 3), get_bits(&key, 18, 3)) {
+            (0, 0) => None,
+            (dice_1 @ 1..=6, dice_2 @ 1..=6) => Some((dice_1 as u8, dice_2 as u8)),
+            _ => return None,
+        };
+        let match_length = get_bits(&key, 21, 15);
+        let score = [get_bits(&key, 36, 15), get_bits(&key, 51, 15)];
+        if !is_valid_score(match_length, score) {
+            return None;
+        }
+        Some(Self {
+            cube_value,
+            cube_owner,
+            on_roll: get_bits(&key, 6, 1) as u8,
+            is_crawford: get_bits(&key, 7, 1) == 1,
+            dice,
+            match_length,
+            score,
+        })
+    }
+}
+
+// "<Position ID>:<Match ID>" as gnubg shows it, the Match ID part is optional and without it
+// `turn` is on roll
+pub fn parse_gnubg_id(text: &str, turn: u8) -> Option<(Position, Option<MatchId>)> {
+    let (position_id, match_id) = match text.trim().split_once(':') {
+        Some((position_id, match_id)) => (position_id, Some(MatchId::parse(match_id)?)),
+        None => (text, None),
+    };
+    let turn = match_id.map_or(turn, |match_id| match_id.on_roll);
+    Some((parse_position_id(position_id, turn)?, match_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::BLACK;
+
+    #[test]
+    fn start_position_reference_id() {
+        assert_eq!(position_id(&Position::new()), "4HPwATDgc/ABMA");
+        let mut start = Position::new();
+        start.turn = BLACK;
+        assert_eq!(parse_position_id("4HPwATDgc/ABMA", BLACK), Some(start));
+        assert_eq!(parse_position_id("4HPwATDgc/AB", WHITE), None);
+        assert_eq!(parse_position_id("////////////AA", WHITE), None);
+    }
+
+    #[test]
+    fn position_round_trip() {
+        let position = Position::from_checkers(
+            &[(1, WHITE, 3), (6, WHITE, 9), (19, BLACK, 4), (20, BLACK, 2)],
+            BLACK,
+            [2, 1],
+            [1, 8],
+        );
+        let id = position_id(&position);
+        assert_eq!(id.len(), 14);
+        assert_eq!(parse_position_id(&id, BLACK), Some(position));
+        // the side on roll is stored last, so the same board reads differently for the other
+        assert_ne!(parse_position_id(&id, WHITE), Some(position));
+    }
+
+    #[test]
+    fn match_id_reference_ids() {
+        // cube 2 owned by player 0, player 1 rolled 5-2, 9 point match at 2-4
+        let reference = MatchId {
+            cube_value: 2,
+            cube_owner: Some(WHITE),
+            on_roll: BLACK,
+            is_crawford: false,
+            dice: Some((5, 2)),
+            match_length: 9,
+            score: [2, 4],
+        };
+        assert_eq!(reference.encode(), "QYkqASAAIAAA");
+        assert_eq!(MatchId::parse("QYkqASAAIAAA"), Some(reference));
+
+        let money = MatchId {
+            cube_value: 1,
+            cube_owner: None,
+            on_roll: BLACK,
+            is_crawford: false,
+            dice: None,
+            match_length: 0,
+            score: [0, 0],
+        };
+        assert_eq!(money.encode(), "cAkAAAAAAAAA");
+        assert_eq!(MatchId::parse("cAkAAAAAAAAA"), Some(money));
+        assert_eq!(MatchId::parse("cAkAAAAA"), None);
+    }
+
+    #[test]
+    fn match_id_rejects_impossible_scores() {
+        let mut state = MatchId {
+            cube_value: 1,
+            cube_owner: None,
+            on_roll: WHITE,
+            is_crawford: false,
+            dice: None,
+            match_length: 5,
+            score: [4, 4],
+        };
+        assert_eq!(MatchId::parse(&state.encode()), Some(state));
+        state.score = [7, 0];
+        assert_eq!(MatchId::parse(&state.encode()), None);
+        state.score = [0, 5];
+        assert_eq!(MatchId::parse(&state.encode()), None);
+        state.match_length = 0;
+        state.score = [1, 0];
+        assert_eq!(MatchId::parse(&state.encode()), None);
+    }
+
+    #[test]
+    fn parses_combined_id() {
+        let (position, match_id) = parse_gnubg_id("4HPwATDgc/ABMA:cAkAAAAAAAAA", WHITE).unwrap();
+        assert_eq!(position.turn, BLACK);
+        assert_eq!(match_id.unwrap().cube_owner, None);
+        let (position, match_id) = parse_gnubg_id(" 4HPwATDgc/ABMA ", WHITE).unwrap();
+        assert_eq!(position, Position::new());
+        assert_eq!(match_id, None);
+        assert!(parse_gnubg_id("4HPwATDgc/ABMA:nonsense", WHITE).is_none());
+    }
+}
@@ -0,0 +1,17 @@
+pub mod analysis;
+pub mod bearoff;
+pub mod bot;
+pub mod cube;
+pub mod dice;
+pub mod fair_dice;
+pub mod gnubg;
+pub mod mat;
+pub mod network;
+pub mod notation;
+pub mod position;
+pub mod replay;
+pub mod rollout;
+pub mod rules;
+pub mod search;
+pub mod sgf;
+pub mod xgid;
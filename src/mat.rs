@@ -0,0 +1,286 @@
+use crate::notation;
+use crate::position::{BLACK, Position, WHITE};
+use crate::replay::{Action, Record, Replay, Snapshot};
+use std::fmt;
+
+// Jellyfish .mat match files, as gnubg, XG and Jellyfish read and write them:
+//
+//  5 point match
+//
+//  Game 1
+//  Alice : 0                         Bob : 0
+//    1) 31: 8/5 6/5                  64: 24/18 13/9
+//    2) Doubles => 2                 Takes
+//
+// Every game starts from the opening position and white is the left player. Moves are
+// written from the mover's side, with 25 for the bar and 0 for off.
+
+const COLUMN: usize = 28;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatGame {
+    pub score: [u32; 2], // before the game
+    pub replay: Replay,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub names: [String; 2],
+    pub length: u32, // 0 for money sessions
+    pub games: Vec<MatGame>,
+}
+
+fn numeric(moves: String) -> String {
+    moves.replace("bar", "25").replace("off", "0")
+}
+
+// "Alice : 0     Bob : 3"
+fn parse_players(line: &str) -> Option<([String; 2], [u32; 2])> {
+    let (white, rest) = line.split_once(':')?;
+    let (white_score, rest) = rest.trim_start().split_once(char::is_whitespace)?;
+    let (black, black_score) = rest.split_once(':')?;
+    Some((
+        [white.trim().to_string(), black.trim().to_string()],
+        [white_score.parse().ok()?, black_score.trim().parse().ok()?],
+    ))
+}
+
+fn is_dice(word: &str) -> bool {
+    matches!(word.as_bytes(), [b'1'..=b'6', b'1'..=b'6', b':'])
+}
+
+// the actions on a numbered line with the column they start at
+fn split_actions(line: &str) -> Vec<(usize, Vec<&str>)> {
+    let mut actions: Vec<(usize, Vec<&str>)> = Vec::new();
+    let mut offset = 0;
+    for word in line.split_whitespace() {
+        let start = offset + line[offset..].find(word).unwrap_or_default();
+        offset = start + word.len();
+        let starts_action = is_dice(word)
+            || matches!(
+                word,
+                "Doubles" | "Takes" | "Accepts" | "Drops" | "Passes" | "Rejects" | "Wins"
+            );
+        match actions.last_mut() {
+            Some((_, words)) if !starts_action => words.push(word),
+            _ => actions.push((start, vec![word])),
+        }
+    }
+    actions
+}
+
+fn parse_action(words: &[&str], color: u8) -> Option<Action> {
+    match words {
+        ["Doubles", ..] => Some(Action::Double),
+        ["Takes" | "Accepts"] => Some(Action::Take),
+        ["Drops" | "Passes" | "Rejects"] => Some(Action::Pass),
+        [dice, moves @ ..] if is_dice(dice) => {
+            let dice = dice.as_bytes();
+            let play = notation::parse_play(&moves.join(" "), color)?;
+            Some(Action::Roll((dice[0] - b'0', dice[1] - b'0'), play))
+        }
+        _ => None,
+    }
+}
+
+fn new_game(score: [u32; 2], records: Vec<Record>) -> Option<MatGame> {
+    let mut position = Position::new();
+    position.turn = records.first().map_or(WHITE, |record| record.color);
+    let replay = Replay {
+        start: Snapshot {
+            position,
+            cube_value: 1,
+            cube_owner: None,
+        },
+        tags: Vec::new(),
+        records,
+    };
+    replay.rebuild()?;
+    Some(MatGame { score, replay })
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, " {} point match", self.length)?;
+        for (number, game) in self.games.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, " Game {}", number + 1)?;
+            let white = format!("{} : {}", self.names[0], game.score[0]);
+            writeln!(f, " {white:<33}{} : {}", self.names[1], game.score[1])?;
+
+            // white's actions start a line, black's go next to them
+            let mut lines: Vec<[String; 2]> = Vec::new();
+            let mut position = game.replay.start.position;
+            let mut cube_value = game.replay.start.cube_value;
+            for record in &game.replay.records {
+                let text = match &record.action {
+                    Action::Roll((die_1, die_2), play) => {
+                        position.turn = record.color;
+                        let moves = numeric(notation::format_moves(&position, play));
+                        position.apply_play(play, &[]);
+                        format!("{die_1}{die_2}: {moves}")
+                    }
+                    Action::Double => {
+                        cube_value *= 2;
+                        format!("Doubles => {cube_value}")
+                    }
+                    Action::Take => "Takes".to_string(),
+                    Action::Pass => "Drops".to_string(),
+                };
+                let column = record.color as usize;
+                match lines.last_mut() {
+                    Some(line) if column == BLACK as usize && line[column].is_empty() => {
+                        line[column] = text
+                    }
+                    _ => {
+                        let mut line = [String::new(), String::new()];
+                        line[column] = text;
+                        lines.push(line);
+                    }
+                }
+            }
+            for (number, [white, black]) in lines.iter().enumerate() {
+                let line = format!("{:>3}) {white:<COLUMN$} {black}", number + 1);
+                writeln!(f, "{}", line.trim_end())?;
+            }
+
+            if let Some((winner, points)) = game.replay.result() {
+                let plural = if points == 1 { "" } else { "s" };
+                let is_match_over =
+                    self.length > 0 && game.score[winner as usize] + points >= self.length;
+                let the_match = if is_match_over { " and the match" } else { "" };
+                let indent = if winner == WHITE { 5 } else { 6 + COLUMN };
+                writeln!(f, "{:indent$}Wins {points} point{plural}{the_match}", "")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Match {
+    // None when a line can't be read or a move can't be made
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut names = [String::new(), String::new()];
+        let mut length = 0;
+        let mut games = Vec::new();
+        // the game being read, its score is None until the players' line
+        let mut game: Option<(Option<[u32; 2]>, Vec<Record>)> = None;
+        for line in content.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(';') {
+                continue;
+            }
+            if let Some(number) = trimmed.strip_suffix("point match") {
+                length = number.trim().parse().ok()?;
+            } else if trimmed.starts_with("Game ") {
+                if let Some((score, records)) = game.take() {
+                    games.push(new_game(score?, records)?);
+                }
+                game = Some((None, Vec::new()));
+            } else if let Some((score @ None, _)) = &mut game {
+                let (players, scores) = parse_players(trimmed)?;
+                names = players;
+                *score = Some(scores);
+            } else if let Some((_, records)) = &mut game
+                && let Some((number, rest)) = trimmed.split_once(')')
+                && number.chars().all(|c| c.is_ascii_digit())
+            {
+                let actions = split_actions(rest);
+                for (index, (start, words)) in actions.iter().enumerate() {
+                    // a single action is black's when it's written in the right column
+                    let color = if actions.len() == 2 {
+                        [WHITE, BLACK][index]
+                    } else if *start > COLUMN / 2 {
+                        BLACK
+                    } else {
+                        WHITE
+                    };
+                    if words[0] == "Wins" {
+                        continue;
+                    }
+                    let action = parse_action(words, color)?;
+                    records.push(Record { color, action });
+                }
+            }
+        }
+        if let Some((score, records)) = game {
+            games.push(new_game(score?, records)?);
+        }
+        Some(Self {
+            names,
+            length,
+            games,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(color: u8, action: Action) -> Record {
+        Record { color, action }
+    }
+
+    #[test]
+    fn writes_and_reads_matches() {
+        let first = new_game(
+            [0, 0],
+            vec![
+                record(WHITE, Action::Roll((3, 1), vec![(8, 5), (6, 5)])),
+                record(BLACK, Action::Roll((6, 4), vec![(1, 7), (12, 16)])),
+                record(WHITE, Action::Double),
+                record(BLACK, Action::Take),
+                record(
+                    WHITE,
+                    Action::Roll((6, 6), vec![(24, 18), (24, 18), (13, 7), (13, 7)]),
+                ),
+                record(BLACK, Action::Double),
+                record(WHITE, Action::Pass),
+            ],
+        )
+        .unwrap();
+        let second = new_game(
+            [0, 2],
+            vec![record(
+                BLACK,
+                Action::Roll((5, 2), vec![(12, 17), (12, 14)]),
+            )],
+        )
+        .unwrap();
+        let played = Match {
+            names: ["Alice".to_string(), "Bob Smith".to_string()],
+            length: 3,
+            games: vec![first, second],
+        };
+        let text = played.to_string();
+        assert!(text.starts_with(" 3 point match\n\n Game 1\n Alice : 0"));
+        assert!(text.contains("\n  1) 31: 8/5 6/5                  64: 24/18 13/9\n"));
+        assert!(text.contains("\n  2) Doubles => 2                 Takes\n"));
+        assert!(text.contains("\n  3) 66: 24/18 24/18 13/7* 13/7   Doubles => 4\n  4) Drops\n"));
+        assert!(text.contains("Wins 2 points\n\n Game 2"));
+        assert!(text.ends_with("\n  1)                              52: 13/8 13/11\n"));
+        assert_eq!(Match::parse(&text), Some(played));
+    }
+
+    #[test]
+    fn reads_jellyfish_moves() {
+        let text = "; [Event \"Test\"]\n\n 1 point match\n\n Game 1\n gnubg : 0    Jelly Fish : 0\n  1)                             43: 24/20 24/21\n  2) 64: 24/18 8/4*              31: 25/22 6/5\n";
+        let parsed = Match::parse(text).unwrap();
+        assert_eq!(parsed.names[1], "Jelly Fish");
+        assert_eq!(parsed.length, 1);
+        let records = &parsed.games[0].replay.records;
+        assert_eq!(records[0].color, BLACK);
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[2],
+            record(BLACK, Action::Roll((3, 1), vec![(0, 3), (19, 20)]))
+        );
+        assert_eq!(
+            records[1],
+            record(WHITE, Action::Roll((6, 4), vec![(24, 18), (8, 4)]))
+        );
+        assert_eq!(parsed.games[0].replay.start.position.turn, BLACK);
+        assert_eq!(Match::parse("1 point match\n Game 1\n nonsense"), None);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::position::{BLACK, Position, WHITE, opponent};
+use crate::rules::dice_from_roll;
+use crate::search::{Evaluator, Probabilities, position_equity};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    fs::{self, read_to_string},
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+// Feed-forward network with one sigmoid hidden layer, trained by TD(lambda) self-play like
+// TD-Gammon. It always rates the position right after `color` has moved, with the opponent
+// about to roll, which is what the search asks for.
+
+pub const INPUTS: usize = 196; // 98 per side, the evaluated side first
+pub const OUTPUTS: usize = 3; // win, win gammon, lose gammon
+pub const DEFAULT_HIDDEN: usize = 40;
+pub const DEFAULT_PATH: &str = "saves/network.txt";
+
+type Outputs = [f64; OUTPUTS];
+
+// per side: 4 units for each point counted from its own home (1, 2, 3 checkers and
+// (n - 3) / 2 for the rest), then bar / 2 and borne off / 15
+pub fn encode(position: &Position, color: u8) -> Vec<f64> {
+    let mut inputs = vec![0.0; INPUTS];
+    for (side, who) in [color, opponent(color)].into_iter().enumerate() {
+        let base = side * INPUTS / 2;
+        for step in 1..=24 {
+            let field = if who == WHITE { step } else { 25 - step };
+            if position.which_color(field) != Some(who) {
+                continue;
+            }
+            let count = position.checker_count(field) as f64;
+            let units = &mut inputs[base + (step - 1) * 4..base + step * 4];
+            units[0] = 1.0;
+            units[1] = if count >= 2.0 { 1.0 } else { 0.0 };
+            units[2] = if count >= 3.0 { 1.0 } else { 0.0 };
+            units[3] = if count > 3.0 {
+                (count - 3.0) / 2.0
+            } else {
+                0.0
+            };
+        }
+        inputs[base + 96] = position.bar[who as usize] as f64 / 2.0;
+        inputs[base + 97] = position.tray[who as usize] as f64 / 15.0;
+    }
+    inputs
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// the same chances seen from the other side
+fn flip(outputs: &Outputs) -> Outputs {
+    [1.0 - outputs[0], outputs[2], outputs[1]]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    hidden: usize,
+    // hidden units first, INPUTS weights and a bias each, then the output units with
+    // `hidden` weights and a bias each
+    weights: Vec<f64>,
+}
+
+impl Network {
+    pub fn new(hidden: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let count = hidden * (INPUTS + 1) + OUTPUTS * (hidden + 1);
+        Self {
+            hidden,
+            weights: (0..count).map(|_| rng.random_range(-0.1..0.1)).collect(),
+        }
+    }
+
+    fn output_offset(&self) -> usize {
+        self.hidden * (INPUTS + 1)
+    }
+
+    fn forward(&self, inputs: &[f64]) -> (Vec<f64>, Outputs) {
+        let hidden: Vec<f64> = self
+            .weights
+            .chunks(INPUTS + 1)
+            .take(self.hidden)
+            .map(|unit| {
+                let sum: f64 = unit.iter().zip(inputs).map(|(w, x)| w * x).sum();
+                sigmoid(sum + unit[INPUTS])
+            })
+            .collect();
+        let mut outputs = [0.0; OUTPUTS];
+        for (output, unit) in outputs
+            .iter_mut()
+            .zip(self.weights[self.output_offset()..].chunks(self.hidden + 1))
+        {
+            let sum: f64 = unit.iter().zip(&hidden).map(|(w, h)| w * h).sum();
+            *output = sigmoid(sum + unit[self.hidden]);
+        }
+        (hidden, outputs)
+    }
+
+    pub fn outputs(&self, position: &Position, color: u8) -> Outputs {
+        self.forward(&encode(position, color)).1
+    }
+
+    // outputs and the gradient of each output over all weights
+    fn gradients(&self, inputs: &[f64]) -> (Outputs, Vec<Vec<f64>>) {
+        let (hidden, outputs) = self.forward(inputs);
+        let offset = self.output_offset();
+        let mut gradients = Vec::with_capacity(OUTPUTS);
+        for (output, &y) in outputs.iter().enumerate() {
+            let mut gradient = vec![0.0; self.weights.len()];
+            let delta = y * (1.0 - y);
+            let unit = offset + output * (self.hidden + 1);
+            for (j, &h) in hidden.iter().enumerate() {
+                gradient[unit + j] = delta * h;
+                let hidden_delta = delta * self.weights[unit + j] * h * (1.0 - h);
+                let hidden_unit = j * (INPUTS + 1);
+                for (i, &x) in inputs.iter().enumerate() {
+                    gradient[hidden_unit + i] = hidden_delta * x;
+                }
+                gradient[hidden_unit + INPUTS] = hidden_delta;
+            }
+            gradient[unit + self.hidden] = delta;
+            gradients.push(gradient);
+        }
+        (outputs, gradients)
+    }
+
+    fn best_play_position(&self, position: &Position, dice: &[u8]) -> Position {
+        let color = position.turn;
+        position
+            .generate_plays(dice)
+            .into_iter()
+            .map(|play| {
+                let mut next = *position;
+                next.apply_play(&play, dice);
+                next
+            })
+            .max_by(|a, b| {
+                let a = position_equity(a, color, self);
+                let b = position_equity(b, color, self);
+                a.total_cmp(&b)
+            })
+            .unwrap_or(*position)
+    }
+
+    // plays one game against itself and learns from it, returns the number of plays
+    pub fn train_game(&mut self, rng: &mut impl Rng, alpha: f64, lambda: f64) -> u32 {
+        let mut position = Position::new();
+        position.turn = if rng.random() { WHITE } else { BLACK };
+        // separate eligibility traces for both sides, one per output
+        let mut traces = [
+            vec![vec![0.0; self.weights.len()]; OUTPUTS],
+            vec![vec![0.0; self.weights.len()]; OUTPUTS],
+        ];
+        let mut previous: Option<(u8, Outputs)> = None;
+        let mut plays = 0;
+        loop {
+            let mover = position.turn;
+            let dice = dice_from_roll(rng.random_range(1..=6), rng.random_range(1..=6));
+            position = self.best_play_position(&position, &dice);
+            plays += 1;
+
+            if position.is_over() {
+                let gammon = if position.win_multiplier(mover) > 1 {
+                    1.0
+                } else {
+                    0.0
+                };
+                if let Some((player, outputs)) = previous {
+                    // the mover won, so the previous side lost
+                    let target = [0.0, 0.0, gammon];
+                    self.update(&traces[player as usize], &target, &outputs, alpha);
+                }
+                return plays;
+            }
+
+            let (outputs, gradients) = self.gradients(&encode(&position, mover));
+            if let Some((player, previous_outputs)) = previous {
+                self.update(
+                    &traces[player as usize],
+                    &flip(&outputs),
+                    &previous_outputs,
+                    alpha,
+                );
+            }
+            for (trace, gradient) in traces[mover as usize].iter_mut().zip(&gradients) {
+                for (e, g) in trace.iter_mut().zip(gradient) {
+                    *e = lambda * *e + g;
+                }
+            }
+            previous = Some((mover, outputs));
+            position.change_turn();
+        }
+    }
+
+    fn update(&mut self, traces: &[Vec<f64>], target: &Outputs, outputs: &Outputs, alpha: f64) {
+        for ((trace, target), output) in traces.iter().zip(target).zip(outputs) {
+            let error = alpha * (target - output);
+            for (w, e) in self.weights.iter_mut().zip(trace) {
+                *w += error * e;
+            }
+        }
+    }
+
+    // "network <inputs> <hidden> <outputs>", then one line of weights per unit
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = format!("network {INPUTS} {} {OUTPUTS}\n", self.hidden);
+        let offset = self.output_offset();
+        let units = self.weights[..offset]
+            .chunks(INPUTS + 1)
+            .chain(self.weights[offset..].chunks(self.hidden + 1));
+        for unit in units {
+            let line: Vec<String> = unit.iter().map(|w| w.to_string()).collect();
+            content.push_str(&line.join(" "));
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let content = read_to_string(path)?;
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut lines = content.lines();
+        let header: Vec<&str> = lines
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let hidden = match header[..] {
+            ["network", inputs, hidden, outputs]
+                if inputs == INPUTS.to_string() && outputs == OUTPUTS.to_string() =>
+            {
+                hidden
+                    .parse()
+                    .map_err(|_| invalid("Invalid hidden layer size"))?
+            }
+            _ => return Err(invalid("Not a network file")),
+        };
+        let weights = lines
+            .flat_map(|line| line.split_whitespace())
+            .map(|w| w.parse().map_err(|_| invalid("Invalid weight")))
+            .collect::<std::io::Result<Vec<f64>>>()?;
+        let network = Self { hidden, weights };
+        if network.weights.len() != hidden * (INPUTS + 1) + OUTPUTS * (hidden + 1) {
+            return Err(invalid("Wrong number of weights"));
+        }
+        Ok(network)
+    }
+}
+
+impl Evaluator for Network {
+    fn equity(&self, position: &Position, color: u8) -> f64 {
+        let [win, win_gammon, lose_gammon] = self.outputs(position, color);
+        2.0 * win - 1.0 + win_gammon - lose_gammon
+    }
+
+    fn probabilities(&self, position: &Position, color: u8) -> Probabilities {
+        let [win, win_gammon, lose_gammon] = self.outputs(position, color);
+        Probabilities {
+            win,
+            win_gammon: win_gammon.min(win),
+            lose_gammon: lose_gammon.min(1.0 - win),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_looks_the_same_for_both_sides() {
+        let position = Position::new();
+        let white = encode(&position, WHITE);
+        assert_eq!(white.len(), INPUTS);
+        assert_eq!(white, encode(&position, BLACK));
+        // two checkers on the 24 point from white's side
+        assert_eq!(white[23 * 4..24 * 4], [1.0, 1.0, 0.0, 0.0]);
+        // five on the 6 point
+        assert_eq!(white[5 * 4..6 * 4], [1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let network = Network::new(5, 1);
+        let path = std::env::temp_dir().join("cli_backgammon_network_test.txt");
+        let path = path.to_str().unwrap();
+        network.save(path).unwrap();
+        let loaded = Network::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded, network);
+        assert!(Network::load("no/such/network.txt").is_err());
+    }
+
+    #[test]
+    fn update_moves_outputs_towards_target() {
+        let mut network = Network::new(8, 2);
+        let inputs = encode(&Position::new(), WHITE);
+        let (before, gradients) = network.gradients(&inputs);
+        let target = [1.0, 1.0, 0.0];
+        network.update(&gradients, &target, &before, 0.5);
+        let after = network.forward(&inputs).1;
+        for output in 0..OUTPUTS {
+            let distance = |outputs: &Outputs| (target[output] - outputs[output]).abs();
+            assert!(distance(&after) < distance(&before));
+        }
+    }
+
+    #[test]
+    fn self_play_game_finishes() {
+        let mut network = Network::new(8, 3);
+        let before = network.clone();
+        let mut rng = StdRng::seed_from_u64(4);
+        assert!(network.train_game(&mut rng, 0.1, 0.7) > 0);
+        assert_ne!(network, before);
+    }
+}
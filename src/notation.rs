@@ -0,0 +1,265 @@
+use crate::position::{Position, WHITE, opponent};
+use crate::rules::{Move, Play};
+
+// Standard move notation, e.g. "24/18 13/11", "bar/22" or "6/off". Points are counted from the
+// mover's side, so everyone's farthest point is 24. A star marks a hit, "(2)" a move made
+// twice, and a checker moved with several dice can be written as "24/13" or "24/18/13".
+// Typed moves may also use 25 for the bar and 0 for off.
+
+const BAR: usize = 25;
+const OFF: usize = 0;
+
+// `color`'s point number of a field, 25 for its bar and 0 for its tray, and the other way round
+pub fn point(field: usize, color: u8) -> usize {
+    if color == WHITE { field } else { 25 - field }
+}
+
+fn point_name(point: usize) -> String {
+    match point {
+        BAR => "bar".to_string(),
+        OFF => "off".to_string(),
+        _ => point.to_string(),
+    }
+}
+
+fn parse_point(text: &str) -> Option<usize> {
+    match text.to_ascii_lowercase().as_str() {
+        "bar" => Some(BAR),
+        "off" => Some(OFF),
+        number => number.parse().ok().filter(|&point| point <= BAR),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotationError {
+    Syntax,
+    Illegal,
+    Ambiguous, // the moves can be played to different positions
+}
+
+impl NotationError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            NotationError::Syntax => "Write moves like 24/18 13/11, bar/22 or 6/off",
+            NotationError::Illegal => "Illegal play",
+            NotationError::Ambiguous => "Ambiguous, name the points in between, e.g. 24/18/13",
+        }
+    }
+}
+
+// (source point, text) of every move in the order they were made
+fn move_texts(position: &Position, play: &[Move]) -> Vec<(usize, String)> {
+    let color = position.turn;
+    let mut position = *position;
+    play.iter()
+        .map(|&(source, destination)| {
+            let is_hit = (1..=24).contains(&destination)
+                && position.which_color(destination) == Some(opponent(color));
+            position.move_checker(source, destination, &mut Vec::new());
+            let text = format!(
+                "{}/{}{}",
+                point_name(point(source, color)),
+                point_name(point(destination, color)),
+                if is_hit { "*" } else { "" }
+            );
+            (point(source, color), text)
+        })
+        .collect()
+}
+
+// `play` by the side on turn in `position`, farthest checkers first and identical moves
+// written once with their count. Moves in that order can always be made one after another.
+pub fn format_play(position: &Position, play: &[Move]) -> String {
+    let mut moves = move_texts(position, play);
+    moves.sort_by_key(|(source, _)| std::cmp::Reverse(*source));
+    let mut counted: Vec<(String, usize)> = Vec::new();
+    for (_, text) in moves {
+        match counted.last_mut() {
+            Some((last, count)) if *last == text => *count += 1,
+            _ => counted.push((text, 1)),
+        }
+    }
+    counted
+        .into_iter()
+        .map(|(text, count)| match count {
+            1 => text,
+            _ => format!("{text}({count})"),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// every move on its own in the order they were made, e.g. for replays
+pub fn format_moves(position: &Position, play: &[Move]) -> String {
+    move_texts(position, play)
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// moves of `color` written as above, one per step of a written path and repeated by its count.
+// They aren't checked against any position.
+pub fn parse_play(text: &str, color: u8) -> Option<Play> {
+    let mut play = Vec::new();
+    for word in text.split_whitespace() {
+        let (path, count) = match word.strip_suffix(')').and_then(|word| word.split_once('(')) {
+            Some((path, count)) => (path, count.parse().ok().filter(|count| *count <= 4)?),
+            None => (word, 1),
+        };
+        let fields = path
+            .split('/')
+            .map(|name| Some(point(parse_point(name.trim_end_matches('*'))?, color)))
+            .collect::<Option<Vec<usize>>>()?;
+        if fields.len() < 2 {
+            return None;
+        }
+        for _ in 0..count {
+            play.extend(fields.windows(2).map(|pair| (pair[0], pair[1])));
+        }
+    }
+    Some(play)
+}
+
+// whether `moves` are exactly the written ones, where one checker may take several moves
+fn covers(written: &[Move], moves: &[Move]) -> bool {
+    let Some((&(source, destination), rest)) = written.split_first() else {
+        return moves.is_empty();
+    };
+    moves
+        .iter()
+        .enumerate()
+        .filter(|(_, mv)| mv.0 == source)
+        .any(|(index, &(_, reached))| {
+            let mut left = moves.to_vec();
+            left.remove(index);
+            if reached == destination {
+                covers(rest, &left)
+            } else {
+                let mut written = vec![(reached, destination)];
+                written.extend_from_slice(rest);
+                covers(&written, &left)
+            }
+        })
+}
+
+// The typed moves of the side on turn as the first moves of one of `plays`, its legal plays
+// from `position`. Moves that weren't typed are left to play.
+pub fn match_play(text: &str, position: &Position, plays: &[Play]) -> Result<Play, NotationError> {
+    let written = parse_play(text, position.turn).ok_or(NotationError::Syntax)?;
+    if written.is_empty() {
+        return Err(NotationError::Syntax);
+    }
+    let mut matches: Vec<(Play, Position)> = Vec::new();
+    for play in plays {
+        for length in 1..=play.len() {
+            let moves = &play[..length];
+            if !covers(&written, moves) {
+                continue;
+            }
+            let mut next = *position;
+            next.apply_play(moves, &[]);
+            if matches.iter().all(|(_, other)| *other != next) {
+                matches.push((moves.to_vec(), next));
+            }
+        }
+    }
+    match &matches[..] {
+        [] => Err(NotationError::Illegal),
+        [(moves, _)] => Ok(moves.clone()),
+        _ => Err(NotationError::Ambiguous),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::BLACK;
+    use crate::rules::dice_from_roll;
+
+    #[test]
+    fn formats_from_the_movers_side() {
+        let position = Position::new();
+        assert_eq!(format_play(&position, &[(8, 5), (6, 5)]), "8/5 6/5");
+        let position =
+            Position::from_checkers(&[(6, WHITE, 14), (22, BLACK, 1)], WHITE, [1, 0], [0, 14]);
+        assert_eq!(
+            format_play(&position, &[(25, 22), (22, 16)]),
+            "bar/22* 22/16"
+        );
+        let mut position =
+            Position::from_checkers(&[(3, WHITE, 1), (23, BLACK, 2)], BLACK, [0, 0], [14, 13]);
+        assert_eq!(format_play(&position, &[(23, 25)]), "2/off");
+        position.turn = WHITE;
+        assert_eq!(format_play(&position, &[(3, 0)]), "3/off");
+    }
+
+    #[test]
+    fn counts_repeated_moves() {
+        let play = [(13, 7), (24, 18), (13, 7), (24, 18)];
+        let position = Position::new();
+        assert_eq!(format_play(&position, &play), "24/18(2) 13/7(2)");
+        assert_eq!(format_moves(&position, &play), "13/7 24/18 13/7 24/18");
+        assert_eq!(
+            parse_play("24/18(2) 13/7", WHITE),
+            Some(vec![(24, 18), (24, 18), (13, 7)])
+        );
+        assert_eq!(
+            parse_play("24/18*/13", WHITE),
+            Some(vec![(24, 18), (18, 13)])
+        );
+        assert_eq!(parse_play("24/18(5)", WHITE), None);
+    }
+
+    #[test]
+    fn matches_typed_moves_to_legal_plays() {
+        let position = Position::new();
+        let plays = position.generate_plays(&dice_from_roll(6, 5));
+        let play = match_play("24/13", &position, &plays).unwrap();
+        assert_eq!(play.len(), 2);
+        assert_eq!(match_play("13/7", &position, &plays), Ok(vec![(13, 7)]));
+        assert_eq!(
+            match_play("24/20", &position, &plays),
+            Err(NotationError::Illegal)
+        );
+        assert_eq!(
+            match_play("", &position, &plays),
+            Err(NotationError::Syntax)
+        );
+
+        // 24/13 can hit on the way or not
+        let position = Position::from_checkers(
+            &[
+                (24, WHITE, 1),
+                (6, WHITE, 14),
+                (18, BLACK, 1),
+                (1, BLACK, 14),
+            ],
+            WHITE,
+            [0, 0],
+            [0, 0],
+        );
+        let plays = position.generate_plays(&dice_from_roll(6, 5));
+        assert_eq!(
+            match_play("24/13", &position, &plays),
+            Err(NotationError::Ambiguous)
+        );
+        assert_eq!(
+            match_play("24/18*/13", &position, &plays),
+            Ok(vec![(24, 18), (18, 13)])
+        );
+    }
+
+    #[test]
+    fn parses_both_colors() {
+        assert_eq!(parse_play("8/5 6/5*", WHITE), Some(vec![(8, 5), (6, 5)]));
+        assert_eq!(
+            parse_play("Bar/22* 2/off", BLACK),
+            Some(vec![(0, 3), (23, 25)])
+        );
+        assert_eq!(parse_play("", WHITE), Some(Vec::new()));
+        assert_eq!(parse_play("8-5", WHITE), None);
+        assert_eq!(parse_play("26/20", WHITE), None);
+        assert_eq!(parse_play("25/22 2/0", BLACK), Some(vec![(0, 3), (23, 25)]));
+    }
+}
@@ -0,0 +1,420 @@
+pub const WHITE: u8 = 0;
+pub const BLACK: u8 = 1;
+
+const START_CHECKERS: [(usize, u8, u8); 8] = [
+    (1, BLACK, 2),
+    (6, WHITE, 5),
+    (8, WHITE, 3),
+    (12, BLACK, 5),
+    (13, WHITE, 5),
+    (17, BLACK, 3),
+    (19, BLACK, 5),
+    (24, WHITE, 2),
+];
+
+pub fn opponent(color: u8) -> u8 {
+    if color == WHITE { BLACK } else { WHITE }
+}
+
+// color is None exactly when the point is empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Point {
+    pub color: Option<u8>,
+    pub count: u8,
+}
+
+impl Point {
+    pub const EMPTY: Point = Point {
+        color: None,
+        count: 0,
+    };
+
+    pub fn new(color: u8, count: u8) -> Self {
+        if count == 0 {
+            return Self::EMPTY;
+        }
+        Self {
+            color: Some(color),
+            count,
+        }
+    }
+
+    pub fn add(&mut self, color: u8) {
+        self.color = Some(color);
+        self.count += 1;
+    }
+
+    pub fn remove(&mut self) {
+        self.count -= 1;
+        if self.count == 0 {
+            self.color = None;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub board: [Point; 24],
+    pub turn: u8,
+    pub bar: [u8; 2],
+    pub tray: [u8; 2],
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Position {
+    pub fn new() -> Self {
+        Self::from_checkers(&START_CHECKERS, WHITE, [0, 0], [0, 0])
+    }
+
+    // checkers given as (field, color, count)
+    pub fn from_checkers(
+        checkers: &[(usize, u8, u8)],
+        turn: u8,
+        bar: [u8; 2],
+        tray: [u8; 2],
+    ) -> Self {
+        let mut board = [Point::EMPTY; 24];
+        for &(field, color, count) in checkers {
+            board[field - 1] = Point::new(color, count);
+        }
+        Self {
+            board,
+            turn,
+            bar,
+            tray,
+        }
+    }
+
+    pub fn change_turn(&mut self) {
+        self.turn = opponent(self.turn);
+    }
+
+    // signed counts used in save files, positive for white and negative for black
+    pub fn board_values(&self) -> [i8; 24] {
+        self.board.map(|point| match point.color {
+            Some(BLACK) => -(point.count as i8),
+            _ => point.count as i8,
+        })
+    }
+
+    // also reads the older encoding where black was stored as count + 15
+    pub fn parse_board(values: &[i32]) -> Option<[Point; 24]> {
+        if values.len() != 24 {
+            return None;
+        }
+        let is_legacy = values.iter().any(|&value| value > 15);
+        let mut board = [Point::EMPTY; 24];
+        for (point, &value) in board.iter_mut().zip(values) {
+            *point = match value {
+                0 => Point::EMPTY,
+                16..=30 if is_legacy => Point::new(BLACK, (value - 15) as u8),
+                1..=15 => Point::new(WHITE, value as u8),
+                -15..=-1 if !is_legacy => Point::new(BLACK, (-value) as u8),
+                _ => return None,
+            };
+        }
+        Some(board)
+    }
+
+    // the first four lines of a save file: board, turn, bar and tray
+    pub fn parse_save(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let mut numbers = || -> Vec<i32> {
+            lines
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter_map(|x| x.parse().ok())
+                .collect()
+        };
+        let board = Self::parse_board(&numbers())?;
+        let turn = match numbers()[..] {
+            [turn] if turn == WHITE as i32 || turn == BLACK as i32 => turn as u8,
+            _ => return None,
+        };
+        let mut counts = || -> Option<[u8; 2]> {
+            match numbers()[..] {
+                [white @ 0..=15, black @ 0..=15] => Some([white as u8, black as u8]),
+                _ => None,
+            }
+        };
+        let bar = counts()?;
+        let tray = counts()?;
+        Some(Self {
+            board,
+            turn,
+            bar,
+            tray,
+        })
+    }
+
+    // checkers of `color` on the board, the bar and the tray
+    pub fn checker_total(&self, color: u8) -> u32 {
+        let board: u32 = self
+            .board
+            .iter()
+            .filter(|point| point.color == Some(color))
+            .map(|point| point.count as u32)
+            .sum();
+        board + self.bar[color as usize] as u32 + self.tray[color as usize] as u32
+    }
+
+    // setting up positions, fields 0 and 25 are the bar or the tray depending on the color.
+    // false when the point holds the other color or all 15 checkers are placed
+    pub fn place_checker(&mut self, field: usize, color: u8) -> bool {
+        if field > 25 || self.checker_total(color) >= 15 {
+            return false;
+        }
+        match field {
+            1..=24 => {
+                let point = &mut self.board[field - 1];
+                if point.color.is_some_and(|other| other != color) {
+                    return false;
+                }
+                point.add(color);
+            }
+            _ if (field == 25) == (color == WHITE) => self.bar[color as usize] += 1,
+            _ => self.tray[color as usize] += 1,
+        }
+        true
+    }
+
+    pub fn remove_checker(&mut self, field: usize, color: u8) -> bool {
+        match field {
+            1..=24 if self.which_color(field) == Some(color) => self.board[field - 1].remove(),
+            0 | 25 => {
+                let count = if (field == 25) == (color == WHITE) {
+                    &mut self.bar[color as usize]
+                } else {
+                    &mut self.tray[color as usize]
+                };
+                if *count == 0 {
+                    return false;
+                }
+                *count -= 1;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn which_color(&self, field: usize) -> Option<u8> {
+        self.board[field - 1].color
+    }
+
+    pub fn checker_count(&self, field: usize) -> u8 {
+        self.board[field - 1].count
+    }
+
+    pub fn are_all_home(&self, color: u8) -> bool {
+        let home = if color == WHITE { 1..=6 } else { 19..=24 };
+        let checker_count: u8 = home
+            .filter(|&field| self.which_color(field) == Some(color))
+            .map(|field| self.checker_count(field))
+            .sum();
+
+        if checker_count == 15 - self.tray[color as usize] {
+            return true;
+        }
+        false
+    }
+
+    pub fn farthest_to_tray(&self, color: u8) -> Option<usize> {
+        if color == WHITE {
+            for field in (1..=6).rev() {
+                if self.which_color(field) == Some(WHITE) {
+                    return Some(field);
+                }
+            }
+        } else {
+            for field in 19..=24 {
+                if self.which_color(field) == Some(BLACK) {
+                    return Some(field);
+                }
+            }
+        }
+        None
+    }
+
+    // pips needed to bear off every checker, the bar counts as 25
+    pub fn pip_count(&self, color: u8) -> u32 {
+        let board: u32 = (1..=24)
+            .filter(|&field| self.which_color(field) == Some(color))
+            .map(|field| {
+                let distance = if color == WHITE { field } else { 25 - field };
+                distance as u32 * self.checker_count(field) as u32
+            })
+            .sum();
+        board + 25 * self.bar[color as usize] as u32
+    }
+
+    // race estimate of the pips really needed: a smooth bear-off wastes about 7 pips, checkers
+    // piled on the lowest points waste more
+    pub fn effective_pip_count(&self, color: u8) -> f64 {
+        let on_point = |point: usize| {
+            let field = if color == WHITE { point } else { 25 - point };
+            if self.which_color(field) == Some(color) {
+                self.checker_count(field) as u32
+            } else {
+                0
+            }
+        };
+        let wastage = 7
+            + 2 * on_point(1).saturating_sub(1)
+            + on_point(2).saturating_sub(1)
+            + on_point(3).saturating_sub(3);
+        (self.pip_count(color) + wastage) as f64
+    }
+
+    // no checker can hit or be hit anymore
+    pub fn is_race(&self) -> bool {
+        let white_back = if self.bar[WHITE as usize] > 0 {
+            25
+        } else {
+            (1..=24)
+                .rev()
+                .find(|&field| self.which_color(field) == Some(WHITE))
+                .unwrap_or(0)
+        };
+        let black_back = if self.bar[BLACK as usize] > 0 {
+            0
+        } else {
+            (1..=24)
+                .find(|&field| self.which_color(field) == Some(BLACK))
+                .unwrap_or(25)
+        };
+        white_back < black_back
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.tray[WHITE as usize] == 15 || self.tray[BLACK as usize] == 15
+    }
+
+    // 1 for a single game, 2 for a gammon, 3 for a backgammon
+    pub fn win_multiplier(&self, winner: u8) -> u32 {
+        let loser = opponent(winner);
+        if self.tray[loser as usize] > 0 {
+            return 1;
+        }
+
+        // loser still on the bar or in winner's home board
+        let mut winner_home = if winner == WHITE { 1..=6 } else { 19..=24 };
+        if self.bar[loser as usize] > 0
+            || winner_home.any(|field| self.which_color(field) == Some(loser))
+        {
+            return 3;
+        }
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(checkers: &[(usize, u8, u8)], bar: [u8; 2], tray: [u8; 2]) -> Position {
+        Position::from_checkers(checkers, WHITE, bar, tray)
+    }
+
+    #[test]
+    fn all_home_uses_own_tray() {
+        let position = position(&[(20, BLACK, 10), (3, WHITE, 15)], [0, 0], [0, 5]);
+        assert!(position.are_all_home(BLACK));
+        assert!(position.are_all_home(WHITE));
+    }
+
+    #[test]
+    fn win_multipliers() {
+        let single = position(&[(20, BLACK, 14)], [0, 0], [15, 1]);
+        assert_eq!(single.win_multiplier(WHITE), 1);
+        let gammon = position(&[(12, BLACK, 15)], [0, 0], [15, 0]);
+        assert_eq!(gammon.win_multiplier(WHITE), 2);
+        let backgammon = position(&[(12, BLACK, 14), (3, BLACK, 1)], [0, 0], [15, 0]);
+        assert_eq!(backgammon.win_multiplier(WHITE), 3);
+        let from_bar = position(&[(12, BLACK, 14)], [0, 1], [15, 0]);
+        assert_eq!(from_bar.win_multiplier(WHITE), 3);
+    }
+
+    #[test]
+    fn pip_counts_and_race() {
+        let start = Position::new();
+        assert_eq!(start.pip_count(WHITE), 167);
+        assert_eq!(start.pip_count(BLACK), 167);
+        assert!(!start.is_race());
+
+        let race = position(&[(5, WHITE, 2), (20, BLACK, 3)], [0, 0], [13, 12]);
+        assert_eq!(race.pip_count(WHITE), 10);
+        assert_eq!(race.pip_count(BLACK), 15);
+        assert!(race.is_race());
+
+        let on_bar = position(&[(5, WHITE, 2), (20, BLACK, 3)], [1, 0], [12, 12]);
+        assert_eq!(on_bar.pip_count(WHITE), 35);
+        assert!(!on_bar.is_race());
+
+        // three checkers piled on black's 1 point waste 4 more pips
+        let piled = position(&[(5, WHITE, 3), (24, BLACK, 3)], [0, 0], [12, 12]);
+        assert_eq!(piled.effective_pip_count(WHITE), 22.0);
+        assert_eq!(piled.effective_pip_count(BLACK), 14.0);
+    }
+
+    #[test]
+    fn places_and_removes_checkers() {
+        let mut setup = position(&[], [0, 0], [0, 0]);
+        assert!(setup.place_checker(6, WHITE));
+        assert!(!setup.place_checker(6, BLACK));
+        assert!(setup.place_checker(25, WHITE));
+        assert!(setup.place_checker(25, BLACK));
+        assert_eq!((setup.bar, setup.tray), ([1, 0], [0, 1]));
+        assert_eq!(setup.checker_total(WHITE), 2);
+        assert!(setup.remove_checker(6, WHITE));
+        assert!(!setup.remove_checker(6, WHITE));
+        assert!(!setup.remove_checker(0, BLACK));
+        for _ in 0..14 {
+            assert!(setup.place_checker(19, BLACK));
+        }
+        assert!(!setup.place_checker(20, BLACK));
+        assert_eq!(setup.checker_total(BLACK), 15);
+    }
+
+    #[test]
+    fn reads_legacy_board() {
+        let legacy = [
+            17, 0, 0, 0, 0, 5, 0, 3, 0, 0, 0, 20, 5, 0, 0, 0, 18, 0, 20, 0, 0, 0, 0, 2,
+        ];
+        assert_eq!(Position::parse_board(&legacy), Some(Position::new().board));
+    }
+
+    #[test]
+    fn board_values_round_trip() {
+        let position = Position::new();
+        let values = position.board_values().map(|value| value as i32);
+        assert_eq!(values[0], -2);
+        assert_eq!(values[23], 2);
+        assert_eq!(Position::parse_board(&values), Some(position.board));
+    }
+
+    #[test]
+    fn parses_save_files() {
+        let save =
+            "-2 0 0 0 0 5 0 3 0 0 0 -5 5 0 0 0 -3 0 -5 0 0 0 0 2 \n1\n0 0 \n0 0 \ncube 1 2\n";
+        let position = Position::parse_save(save).unwrap();
+        assert_eq!(position.board, Position::new().board);
+        assert_eq!(position.turn, BLACK);
+        assert_eq!(Position::parse_save("0 0\n0\n0 0\n0 0\n"), None);
+        assert_eq!(Position::parse_save(&save.replace("\n1\n", "\n3\n")), None);
+    }
+
+    #[test]
+    fn rejects_bad_boards() {
+        assert_eq!(Position::parse_board(&[0; 23]), None);
+        let mut mixed = [0; 24];
+        mixed[0] = 17;
+        mixed[1] = -2;
+        assert_eq!(Position::parse_board(&mixed), None);
+    }
+}
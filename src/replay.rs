@@ -0,0 +1,568 @@
+use crate::analysis::Turn;
+use crate::cube::{self, MAX_CUBE_VALUE};
+use crate::notation;
+use crate::position::{BLACK, Position, WHITE, opponent};
+use crate::rules::{Move, Play, dice_from_roll};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Lines;
+
+// Move-list replays. A "replay" line, the starting position as in a save file (board, turn,
+// bar, tray and cube lines) and other tagged lines such as the dice seed, then one line per
+// action in the order they happened:
+//
+//   white 31 8/5 6/5
+//   black double
+//   white take
+//
+// Every position is rebuilt by playing the moves. Older replays stored a snapshot after each
+// checker move instead, `convert` works the moves out from them.
+
+const HEADER: &str = "replay";
+const CENTERED: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub position: Position,
+    pub cube_value: u32,
+    pub cube_owner: Option<u8>, // None when centered
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Roll((u8, u8), Play), // a die is 0 when a converted replay couldn't tell it
+    Double,
+    Take,
+    Pass,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub color: u8,
+    pub action: Action,
+}
+
+// a turn and the index of the snapshot it started from
+pub type IndexedTurn = (usize, Turn);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub start: Snapshot,
+    pub tags: Vec<String>, // other tagged lines, kept as they are
+    pub records: Vec<Record>,
+}
+
+fn color_name(color: u8) -> &'static str {
+    if color == WHITE { "white" } else { "black" }
+}
+
+fn is_tagged(line: &str) -> bool {
+    line.split_whitespace()
+        .next()
+        .is_some_and(|tag| tag.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+// "cube <value> <owner>", owner 2 is centered
+fn parse_cube(words: &[&str]) -> Option<(u32, Option<u8>)> {
+    let [value, owner] = words else {
+        return None;
+    };
+    let owner = match owner.parse().ok()? {
+        CENTERED => None,
+        owner @ 0..=1 => Some(owner as u8),
+        _ => return None,
+    };
+    let value = value
+        .parse()
+        .ok()
+        .filter(|&value| cube::is_valid_cube_value(value))?;
+    Some((value, owner))
+}
+
+// board, turn, bar and tray lines
+fn read_position(lines: &mut Peekable<Lines>) -> Option<Position> {
+    let text: Vec<&str> = lines.take(4).collect();
+    Position::parse_save(&text.join("\n"))
+}
+
+fn parse_dice(text: &str) -> Option<(u8, u8)> {
+    match text.as_bytes() {
+        [die_1 @ b'0'..=b'6', die_2 @ b'0'..=b'6'] => Some((die_1 - b'0', die_2 - b'0')),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{HEADER}")?;
+        let position = &self.start.position;
+        for field in position.board_values() {
+            write!(f, "{field} ")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{}", position.turn)?;
+        writeln!(f, "{} {}", position.bar[0], position.bar[1])?;
+        writeln!(f, "{} {}", position.tray[0], position.tray[1])?;
+        let owner = self.start.cube_owner.map_or(CENTERED, u32::from);
+        writeln!(f, "cube {} {owner}", self.start.cube_value)?;
+        for tag in &self.tags {
+            writeln!(f, "{tag}")?;
+        }
+
+        let mut position = *position;
+        for record in &self.records {
+            write!(f, "{}", color_name(record.color))?;
+            match &record.action {
+                Action::Roll((die_1, die_2), play) => {
+                    position.turn = record.color;
+                    write!(f, " {die_1}{die_2}")?;
+                    if !play.is_empty() {
+                        write!(f, " {}", notation::format_moves(&position, play))?;
+                    }
+                    position.apply_play(play, &[]);
+                }
+                Action::Double => write!(f, " double")?,
+                Action::Take => write!(f, " take")?,
+                Action::Pass => write!(f, " pass")?,
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Replay {
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines().peekable();
+        if lines.next()?.trim() != HEADER {
+            return None;
+        }
+        let position = read_position(&mut lines)?;
+        let mut start = Snapshot {
+            position,
+            cube_value: 1,
+            cube_owner: None,
+        };
+        let mut tags = Vec::new();
+        let mut records = Vec::new();
+        for line in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let color = match words[..] {
+                [] => continue,
+                ["white", ..] => WHITE,
+                ["black", ..] => BLACK,
+                ["cube", ..] if records.is_empty() => {
+                    (start.cube_value, start.cube_owner) = parse_cube(&words[1..])?;
+                    continue;
+                }
+                _ => {
+                    tags.push(line.to_string());
+                    continue;
+                }
+            };
+            let action = match *words.get(1)? {
+                "double" => Action::Double,
+                "take" => Action::Take,
+                "pass" => Action::Pass,
+                dice => Action::Roll(
+                    parse_dice(dice)?,
+                    notation::parse_play(&words[2..].join(" "), color)?,
+                ),
+            };
+            records.push(Record { color, action });
+        }
+        let replay = Self {
+            start,
+            tags,
+            records,
+        };
+        // every move has to be possible
+        replay.rebuild()?;
+        Some(replay)
+    }
+
+    // Every position the replay viewer steps through: the start and the positions after each
+    // checker move and each take, and the turns with both dice known. None when a move
+    // can't be made.
+    pub fn rebuild(&self) -> Option<(Vec<Snapshot>, Vec<IndexedTurn>)> {
+        let mut current = self.start;
+        let mut snapshots = vec![current];
+        let mut turns = Vec::new();
+        for record in &self.records {
+            match &record.action {
+                Action::Roll(dice, play) => {
+                    current.position.turn = record.color;
+                    if dice.0 > 0 && dice.1 > 0 {
+                        let turn = Turn {
+                            position: current.position,
+                            dice: *dice,
+                            play: play.clone(),
+                        };
+                        turns.push((snapshots.len() - 1, turn));
+                    }
+                    let before = current.position;
+                    for &(source, destination) in play {
+                        if !current.position.can_move_checker(source, destination) {
+                            return None;
+                        }
+                        current
+                            .position
+                            .move_checker(source, destination, &mut Vec::new());
+                        snapshots.push(current);
+                    }
+                    if dice.0 > 0 && dice.1 > 0 && !is_legal(&before, *dice, &current.position) {
+                        return None;
+                    }
+                }
+                Action::Take => {
+                    if current.cube_value >= MAX_CUBE_VALUE {
+                        return None;
+                    }
+                    current.cube_value *= 2;
+                    current.cube_owner = Some(record.color);
+                    snapshots.push(current);
+                }
+                // the doubler stays on turn
+                Action::Double => current.position.turn = record.color,
+                Action::Pass => {}
+            }
+        }
+        Some((snapshots, turns))
+    }
+
+    // the index of the snapshot each record ends at, as `rebuild` numbers them
+    pub fn record_snapshots(&self) -> Vec<usize> {
+        let mut index = 0;
+        self.records
+            .iter()
+            .map(|record| {
+                index += match &record.action {
+                    Action::Roll(_, play) => play.len(),
+                    Action::Take => 1,
+                    Action::Double | Action::Pass => 0,
+                };
+                index
+            })
+            .collect()
+    }
+
+    // the winner and the points won, None while the game goes on
+    pub fn result(&self) -> Option<(u8, u32)> {
+        let (snapshots, _) = self.rebuild()?;
+        let last = snapshots.last()?;
+        if let Some(Record {
+            color,
+            action: Action::Pass,
+        }) = self.records.last()
+        {
+            return Some((opponent(*color), last.cube_value));
+        }
+        [WHITE, BLACK]
+            .into_iter()
+            .find(|&color| last.position.tray[color as usize] == 15)
+            .map(|winner| {
+                (
+                    winner,
+                    last.position.win_multiplier(winner) * last.cube_value,
+                )
+            })
+    }
+
+    // An older replay with a snapshot after every checker move. Each move is the one that
+    // leads from a snapshot to the next, a new turn starts when the other side moves or
+    // the moves no longer fit one roll. Dice come from the replay's `turn` lines, older
+    // replays don't have them so they are read from the moves, a bear-off counting as the
+    // smallest die it could have used.
+    pub fn convert(content: &str) -> Option<Self> {
+        let mut lines = content.lines().peekable();
+        let mut snapshots = Vec::new();
+        let mut known_dice = Vec::new();
+        let mut tags = Vec::new();
+        while let Some(position) = read_position(&mut lines) {
+            let mut snapshot = Snapshot {
+                position,
+                cube_value: 1,
+                cube_owner: None,
+            };
+            while let Some(line) = lines.next_if(|line| is_tagged(line)) {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                match words[..] {
+                    ["cube", ..] => {
+                        (snapshot.cube_value, snapshot.cube_owner) = parse_cube(&words[1..])?;
+                    }
+                    // "turn <snapshot> <color> <die> <die> <source> <destination> ..."
+                    ["turn", index, color, die_1, die_2, ..] => {
+                        if let (Ok(index), Ok(color), Ok(die_1), Ok(die_2)) =
+                            (index.parse(), color.parse(), die_1.parse(), die_2.parse())
+                        {
+                            known_dice.push(((index, color), (die_1, die_2)));
+                        }
+                    }
+                    _ => tags.push(line.to_string()),
+                }
+            }
+            snapshots.push(snapshot);
+        }
+        let dice = |index: usize, color: u8, play: &[Move]| {
+            known_dice
+                .iter()
+                .find(|(key, _)| *key == (index, color))
+                .map_or_else(|| guess_dice(play), |(_, dice)| *dice)
+        };
+
+        let mut records = Vec::new();
+        let mut last_roll = None;
+        let mut finish_turn = |records: &mut Vec<Record>, turn: Option<(u8, usize, Play)>| {
+            let Some((color, index, play)) = turn else {
+                return;
+            };
+            // the other side couldn't move in between
+            if last_roll == Some(color) {
+                let action = Action::Roll(dice(index, opponent(color), &[]), Vec::new());
+                records.push(Record {
+                    color: opponent(color),
+                    action,
+                });
+            }
+            last_roll = Some(color);
+            let action = Action::Roll(dice(index, color, &play), play);
+            records.push(Record { color, action });
+        };
+        let mut turn: Option<(u8, usize, Play)> = None;
+        for (index, pair) in snapshots.windows(2).enumerate() {
+            let (before, after) = (&pair[0], &pair[1]);
+            if (before.cube_value, before.cube_owner) != (after.cube_value, after.cube_owner) {
+                finish_turn(&mut records, turn.take());
+                let taker = after.cube_owner?;
+                records.push(Record {
+                    color: opponent(taker),
+                    action: Action::Double,
+                });
+                records.push(Record {
+                    color: taker,
+                    action: Action::Take,
+                });
+                continue;
+            }
+            let color = after.position.turn;
+            let mv = find_move(&before.position, &after.position)?;
+            match &mut turn {
+                Some((turn_color, _, play)) if *turn_color == color && fits(play, mv, color) => {
+                    play.push(mv)
+                }
+                _ => {
+                    finish_turn(&mut records, turn.take());
+                    turn = Some((color, index, vec![mv]));
+                }
+            }
+        }
+        finish_turn(&mut records, turn);
+
+        // older versions didn't always make the dice be used in full, those turns get
+        // unknown dice
+        let start = *snapshots.first()?;
+        let mut position = start.position;
+        for record in &mut records {
+            if let Action::Roll(dice, play) = &mut record.action {
+                position.turn = record.color;
+                let before = position;
+                position.apply_play(play, &[]);
+                if dice.0 > 0 && dice.1 > 0 && !is_legal(&before, *dice, &position) {
+                    *dice = (0, 0);
+                }
+            }
+        }
+        Some(Self {
+            start,
+            tags,
+            records,
+        })
+    }
+}
+
+// whether one of the legal plays for `dice` of the side on turn leads from `before` to `after`
+fn is_legal(before: &Position, (die_1, die_2): (u8, u8), after: &Position) -> bool {
+    let plays = before.generate_plays(&dice_from_roll(die_1, die_2));
+    if plays.iter().all(Vec::is_empty) {
+        return before == after;
+    }
+    plays.iter().any(|play| {
+        let mut next = *before;
+        next.apply_play(play, &[]);
+        next == *after
+    })
+}
+
+// the checker move of `after`'s side on turn that leads from `before` to `after`
+fn find_move(before: &Position, after: &Position) -> Option<Move> {
+    let mut position = *before;
+    position.turn = after.turn;
+    (0..=25)
+        .flat_map(|source| (0..=25).map(move |destination| (source, destination)))
+        .filter(|&(source, destination)| position.can_move_checker(source, destination))
+        .find(|&(source, destination)| {
+            let mut next = position;
+            next.move_checker(source, destination, &mut Vec::new());
+            next == *after
+        })
+}
+
+fn distance(&(source, destination): &Move) -> usize {
+    source.abs_diff(destination)
+}
+
+// whether one more move still fits a single roll, only doubles play more than two
+fn fits(play: &[Move], mv: Move, color: u8) -> bool {
+    if play.len() < 2 {
+        return true;
+    }
+    if play.len() >= 4 {
+        return false;
+    }
+    let tray = if color == WHITE { 0 } else { 25 };
+    let moves = || play.iter().chain([&mv]);
+    let die = moves().map(distance).max().unwrap_or_default();
+    moves().all(|mv| distance(mv) == die || mv.1 == tray)
+}
+
+fn guess_dice(play: &[Move]) -> (u8, u8) {
+    let dice: Vec<u8> = play.iter().map(|mv| distance(mv) as u8).collect();
+    match dice[..] {
+        [] => (0, 0),
+        [die] => (die, 0),
+        [die_1, die_2] => (die_1, die_2),
+        _ => {
+            let die = dice.iter().copied().max().unwrap_or_default();
+            (die, die)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opening_game() -> Replay {
+        let record = |color, action| Record { color, action };
+        Replay {
+            start: Snapshot {
+                position: Position::new(),
+                cube_value: 1,
+                cube_owner: None,
+            },
+            tags: vec!["dice 42 0".to_string()],
+            records: vec![
+                record(WHITE, Action::Roll((3, 1), vec![(8, 5), (6, 5)])),
+                record(BLACK, Action::Roll((6, 4), vec![(1, 7), (12, 16)])),
+                record(WHITE, Action::Double),
+                record(BLACK, Action::Take),
+            ],
+        }
+    }
+
+    #[test]
+    fn writes_and_reads_move_lists() {
+        let replay = opening_game();
+        let text = replay.to_string();
+        assert_eq!(text.lines().count(), 11);
+        assert!(text.contains("\nwhite 31 8/5 6/5\nblack 64 24/18 13/9\nwhite double\n"));
+        assert_eq!(Replay::parse(&text), Some(replay.clone()));
+
+        let (snapshots, turns) = replay.rebuild().unwrap();
+        assert_eq!(snapshots.len(), 6);
+        assert_eq!(snapshots[5].cube_value, 2);
+        assert_eq!(snapshots[5].cube_owner, Some(BLACK));
+        assert_eq!(turns[1].0, 2);
+        assert_eq!(turns[1].1.position.board, snapshots[2].position.board);
+        assert_eq!(replay.record_snapshots(), vec![2, 4, 4, 5]);
+        assert_eq!(Replay::parse("cube 1 2"), None);
+    }
+
+    #[test]
+    fn cube_values_are_powers_of_two_up_to_64() {
+        let text = opening_game().to_string();
+        for value in ["0", "3", "128"] {
+            let bad = text.replace("cube 1 2", &format!("cube {value} 2"));
+            assert_eq!(Replay::parse(&bad), None, "{value}");
+        }
+        let mut replay = opening_game();
+        replay.start.cube_value = 32;
+        assert_eq!(replay.rebuild().unwrap().0[5].cube_value, 64);
+        replay.start.cube_value = 64;
+        assert_eq!(replay.rebuild(), None);
+    }
+
+    #[test]
+    fn rejects_impossible_moves() {
+        let mut replay = opening_game();
+        replay.records.push(Record {
+            color: WHITE,
+            action: Action::Roll((6, 4), vec![(5, 0)]),
+        });
+        assert_eq!(replay.rebuild(), None);
+
+        // possible moves, but not for the dice
+        replay.records.pop();
+        replay.records.push(Record {
+            color: WHITE,
+            action: Action::Roll((3, 1), vec![(24, 18)]),
+        });
+        assert_eq!(replay.rebuild(), None);
+        let text = format!("{}", opening_game()) + "white 31 24/18\n";
+        assert_eq!(Replay::parse(&text), None);
+    }
+
+    #[test]
+    fn converts_snapshot_replays() {
+        // white 31, black can't move, white 64 hitting, then a double and a take
+        let mut snapshots = Vec::new();
+        let mut position = Position::from_checkers(
+            &[
+                (8, WHITE, 5),
+                (6, WHITE, 10),
+                (4, BLACK, 1),
+                (19, BLACK, 14),
+            ],
+            WHITE,
+            [0, 0],
+            [0, 0],
+        );
+        let mut old = String::new();
+        let mut push = |position: &Position, cube: &str| {
+            snapshots.push(*position);
+            let values: Vec<String> = position.board_values().map(|v| v.to_string()).to_vec();
+            old += &format!(
+                "{}\n{}\n0 {}\n0 0\n",
+                values.join(" "),
+                position.turn,
+                position.bar[1]
+            );
+            old += cube;
+        };
+        push(&position, "cube 1 2\ndice 42 0\n");
+        for mv in [(8, 5), (6, 5), (8, 4), (8, 2)] {
+            position.move_checker(mv.0, mv.1, &mut Vec::new());
+            push(&position, "");
+        }
+        push(&position, "cube 2 1\n");
+
+        let replay = Replay::convert(&old).unwrap();
+        assert_eq!(replay.start.position, snapshots[0]);
+        assert_eq!(replay.tags, vec!["dice 42 0".to_string()]);
+        let record = |color, action| Record { color, action };
+        assert_eq!(
+            replay.records,
+            vec![
+                record(WHITE, Action::Roll((3, 1), vec![(8, 5), (6, 5)])),
+                record(BLACK, Action::Roll((0, 0), Vec::new())),
+                record(WHITE, Action::Roll((4, 6), vec![(8, 4), (8, 2)])),
+                record(WHITE, Action::Double),
+                record(BLACK, Action::Take),
+            ]
+        );
+        let (rebuilt, turns) = replay.rebuild().unwrap();
+        let rebuilt: Vec<Position> = rebuilt.iter().map(|snapshot| snapshot.position).collect();
+        assert_eq!(rebuilt, snapshots);
+        assert_eq!(turns.len(), 2);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::bearoff::BearoffDatabase;
+use crate::bot::{self, Difficulty};
+use crate::position::{Position, opponent};
+use crate::rules::{Play, dice_from_roll};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use std::thread;
+
+// Monte Carlo rollouts: the position is played out many times by a bot policy. Every trial
+// gets its own generator derived from the seed, so results don't depend on the number of
+// threads. As variance reduction the first two rolls are stratified: over 1296 trials every
+// combination of the first two rolls is played exactly once.
+
+#[derive(Debug, Clone)]
+pub struct RolloutConfig {
+    pub games: u32,
+    pub seed: u64,
+    pub threads: usize,
+    pub policy: Difficulty,
+    pub stratified: bool,
+}
+
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        Self {
+            games: 1296,
+            seed: 0,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            policy: Difficulty::Expert(1),
+            stratified: true,
+        }
+    }
+}
+
+// mean and the half width of its 95% confidence interval
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Estimate {
+    pub mean: f64,
+    pub interval: f64,
+}
+
+impl Estimate {
+    fn from_sums(sum: f64, squares: f64, games: u32) -> Self {
+        let n = games as f64;
+        let mean = sum / n;
+        let variance = if games > 1 {
+            ((squares - n * mean * mean) / (n - 1.0)).max(0.0)
+        } else {
+            0.0
+        };
+        Self {
+            mean,
+            interval: 1.96 * (variance / n).sqrt(),
+        }
+    }
+}
+
+// chances for the side the rollout was made for, gammons include backgammons
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RolloutResult {
+    pub games: u32,
+    pub win: Estimate,
+    pub win_gammon: Estimate,
+    pub win_backgammon: Estimate,
+    pub lose_gammon: Estimate,
+    pub lose_backgammon: Estimate,
+    pub equity: Estimate, // cubeless, points per game
+}
+
+impl RolloutResult {
+    // the same result seen by the other side
+    pub fn flipped(&self) -> Self {
+        Self {
+            games: self.games,
+            win: Estimate {
+                mean: 1.0 - self.win.mean,
+                interval: self.win.interval,
+            },
+            win_gammon: self.lose_gammon,
+            win_backgammon: self.lose_backgammon,
+            lose_gammon: self.win_gammon,
+            lose_backgammon: self.win_backgammon,
+            equity: Estimate {
+                mean: -self.equity.mean,
+                interval: self.equity.interval,
+            },
+        }
+    }
+}
+
+// sums and sums of squares of win, win gammon, win backgammon, lose gammon, lose
+// backgammon and equity
+type Sums = [(f64, f64); 6];
+
+fn stratified_roll(trial: u32, roll: u32) -> (u8, u8) {
+    let combination = (trial / 36u32.pow(roll)) % 36;
+    ((combination / 6) as u8 + 1, (combination % 6) as u8 + 1)
+}
+
+// points won by `color`, who is on roll, negative for a loss
+fn play_out(
+    position: &Position,
+    trial: u32,
+    config: &RolloutConfig,
+    bearoff: Option<&BearoffDatabase>,
+) -> i32 {
+    let mut rng =
+        StdRng::seed_from_u64(config.seed ^ (trial as u64).wrapping_mul(0x9e3779b97f4a7c15));
+    let color = position.turn;
+    let mut position = *position;
+    let mut roll = 0;
+    loop {
+        let (dice_1, dice_2) = if config.stratified && roll < 2 {
+            stratified_roll(trial, roll)
+        } else {
+            (rng.random_range(1..=6), rng.random_range(1..=6))
+        };
+        roll += 1;
+        let dice = dice_from_roll(dice_1, dice_2);
+        // random play has to come from the trial's generator to stay reproducible
+        let play: Play = if matches!(config.policy, Difficulty::Random) {
+            position
+                .generate_plays(&dice)
+                .choose(&mut rng)
+                .cloned()
+                .unwrap_or_default()
+        } else {
+            bot::choose_play(&position, &dice, &config.policy, bearoff)
+        };
+        position.apply_play(&play, &dice);
+        if position.is_over() {
+            let points = position.win_multiplier(position.turn) as i32;
+            return if position.turn == color {
+                points
+            } else {
+                -points
+            };
+        }
+        position.change_turn();
+    }
+}
+
+// rolls out `position` for the side on roll
+pub fn rollout(
+    position: &Position,
+    config: &RolloutConfig,
+    bearoff: Option<&BearoffDatabase>,
+) -> RolloutResult {
+    assert!(config.games > 0, "a rollout needs at least one game");
+    let threads = config.threads.clamp(1, config.games as usize);
+    let sums: Vec<Sums> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|first| {
+                scope.spawn(move || {
+                    let mut sums: Sums = [(0.0, 0.0); 6];
+                    for trial in (first as u32..config.games).step_by(threads) {
+                        let points = play_out(position, trial, config, bearoff);
+                        let values = [
+                            (points > 0) as u8 as f64,
+                            (points >= 2) as u8 as f64,
+                            (points >= 3) as u8 as f64,
+                            (points <= -2) as u8 as f64,
+                            (points <= -3) as u8 as f64,
+                            points as f64,
+                        ];
+                        for (sum, value) in sums.iter_mut().zip(values) {
+                            sum.0 += value;
+                            sum.1 += value * value;
+                        }
+                    }
+                    sums
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut total: Sums = [(0.0, 0.0); 6];
+    for part in sums {
+        for (sum, (value, square)) in total.iter_mut().zip(part) {
+            sum.0 += value;
+            sum.1 += square;
+        }
+    }
+    let [
+        win,
+        win_gammon,
+        win_backgammon,
+        lose_gammon,
+        lose_backgammon,
+        equity,
+    ] = total.map(|(sum, squares)| Estimate::from_sums(sum, squares, config.games));
+    RolloutResult {
+        games: config.games,
+        win,
+        win_gammon,
+        win_backgammon,
+        lose_gammon,
+        lose_backgammon,
+        equity,
+    }
+}
+
+// rolls out the position after each play, results are for the side making the play
+pub fn rollout_plays(
+    position: &Position,
+    dice: &[u8],
+    plays: &[Play],
+    config: &RolloutConfig,
+    bearoff: Option<&BearoffDatabase>,
+) -> Vec<RolloutResult> {
+    plays
+        .iter()
+        .map(|play| {
+            let mut next = *position;
+            next.apply_play(play, dice);
+            if next.is_over() {
+                let points = next.win_multiplier(position.turn) as f64;
+                let certain = |hit: bool| Estimate {
+                    mean: hit as u8 as f64,
+                    interval: 0.0,
+                };
+                return RolloutResult {
+                    games: 0,
+                    win: certain(true),
+                    win_gammon: certain(points >= 2.0),
+                    win_backgammon: certain(points >= 3.0),
+                    lose_gammon: certain(false),
+                    lose_backgammon: certain(false),
+                    equity: Estimate {
+                        mean: points,
+                        interval: 0.0,
+                    },
+                };
+            }
+            next.turn = opponent(position.turn);
+            rollout(&next, config, bearoff).flipped()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{BLACK, WHITE};
+
+    fn config(games: u32, threads: usize) -> RolloutConfig {
+        RolloutConfig {
+            games,
+            seed: 11,
+            threads,
+            policy: Difficulty::Greedy,
+            stratified: true,
+        }
+    }
+
+    #[test]
+    fn stratified_rolls_cover_all_combinations() {
+        let mut seen: Vec<((u8, u8), (u8, u8))> = (0..1296)
+            .map(|trial| (stratified_roll(trial, 0), stratified_roll(trial, 1)))
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 1296);
+    }
+
+    #[test]
+    fn same_seed_same_result_on_any_thread_count() {
+        let position = Position::new();
+        let single = rollout(&position, &config(40, 1), None);
+        let parallel = rollout(&position, &config(40, 3), None);
+        assert_eq!(single, parallel);
+        assert!(single.win.mean > 0.0 && single.win.mean < 1.0);
+        assert!(single.win.interval > 0.0);
+        assert!(single.win_gammon.mean <= single.win.mean);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one game")]
+    fn no_games_is_rejected() {
+        rollout(&Position::new(), &config(0, 1), None);
+    }
+
+    #[test]
+    fn certain_win_has_no_interval() {
+        // white needs one roll, black two
+        let position = Position::from_checkers(
+            &[(1, WHITE, 2), (24, BLACK, 1), (19, BLACK, 1)],
+            WHITE,
+            [0, 0],
+            [13, 13],
+        );
+        let result = rollout(&position, &config(30, 2), None);
+        assert_eq!(result.win.mean, 1.0);
+        assert_eq!(result.win.interval, 0.0);
+        assert_eq!(result.equity.mean, 1.0);
+        assert_eq!(result.flipped().equity.mean, -1.0);
+    }
+
+    #[test]
+    fn compares_plays_for_the_mover() {
+        let position = Position::new();
+        let dice = dice_from_roll(3, 1);
+        let plays = position.generate_plays(&dice)[..2].to_vec();
+        let results = rollout_plays(&position, &dice, &plays, &config(12, 2), None);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.games == 12));
+    }
+}
@@ -0,0 +1,535 @@
+use crate::position::{Point, Position, WHITE};
+
+// (source, destination), 25 and 0 are the bar for white and black, 0 and 25 their trays
+pub type Move = (usize, usize);
+pub type Play = Vec<Move>;
+
+// doubles are played four times
+pub fn dice_from_roll(dice_1: u8, dice_2: u8) -> Vec<u8> {
+    if dice_1 == dice_2 {
+        vec![dice_1; 4]
+    } else {
+        vec![dice_1, dice_2]
+    }
+}
+
+// next moves that continue some maximal play from what was already played this turn
+pub fn legal_moves(plays: &[Play], played: &[Move]) -> Vec<Move> {
+    let depth = played.len();
+    let mut moves: Vec<Move> = plays
+        .iter()
+        .filter(|play| play.len() > depth && play.starts_with(played))
+        .map(|play| play[depth])
+        .collect();
+    moves.sort();
+    moves.dedup();
+    moves
+}
+
+impl Position {
+    fn validate_home(&self, source: usize, destination: usize) -> bool {
+        // 0 and 25 represent tray for white and black, respectively
+        let valid_destination = if self.turn == WHITE { 0 } else { 25 };
+        if destination != valid_destination || !(1..=24).contains(&source) {
+            return false;
+        }
+
+        // has checker on source field
+        if self.checker_count(source) == 0 {
+            return false;
+        }
+
+        // right color
+        if let Some(color) = self.which_color(source)
+            && color != self.turn
+        {
+            return false;
+        }
+
+        // right direction
+        if self.turn == WHITE {
+            if destination >= source {
+                return false;
+            }
+        } else if destination <= source {
+            return false;
+        }
+        true
+    }
+
+    fn validate_bar(&self, source: usize, destination: usize) -> bool {
+        // 25 and 0 represent bar for white and black, respectively
+        let valid_source = if self.turn == WHITE { 25 } else { 0 };
+        if source != valid_source || !(1..=24).contains(&destination) {
+            return false;
+        }
+
+        // right color
+        if let Some(color) = self.which_color(destination)
+            && color != self.turn
+            && self.checker_count(destination) > 1
+        {
+            return false;
+        }
+
+        // right direction
+        if self.turn == WHITE {
+            if destination >= source {
+                return false;
+            }
+        } else if destination <= source {
+            return false;
+        }
+        true
+    }
+
+    pub fn is_move_valid(&self, source: usize, destination: usize) -> bool {
+        if self.bar[self.turn as usize] > 0 {
+            return self.validate_bar(source, destination);
+        }
+
+        // withing board bounds
+        if !(1..=24).contains(&source) || !(1..=24).contains(&destination) {
+            return false;
+        }
+
+        // has checker on source field
+        if self.checker_count(source) == 0 {
+            return false;
+        }
+
+        // right color
+        if let Some(color) = self.which_color(source)
+            && color != self.turn
+        {
+            return false;
+        }
+        if let Some(color) = self.which_color(destination)
+            && color != self.turn
+            && self.checker_count(destination) > 1
+        {
+            return false;
+        }
+
+        // right direction
+        if self.turn == WHITE {
+            if destination >= source {
+                return false;
+            }
+        } else if destination <= source {
+            return false;
+        }
+        true
+    }
+
+    // a single checker move of the side on turn with whatever die it takes, bearing off included
+    pub fn can_move_checker(&self, source: usize, destination: usize) -> bool {
+        self.is_move_valid(source, destination)
+            || (self.bar[self.turn as usize] == 0
+                && self.are_all_home(self.turn)
+                && self.validate_home(source, destination))
+    }
+
+    fn add_moves_to_tray_dice(&self, moves: &mut Vec<Move>, destination: usize, dice: usize) {
+        // exact roll from the matching point
+        let source = if self.turn == WHITE { dice } else { 25 - dice };
+        if self.validate_home(source, destination) {
+            moves.push((source, destination));
+            return;
+        }
+
+        // higher roll only from the farthest checker, never past checkers on higher points
+        if let Some(field) = self.farthest_to_tray(self.turn)
+            && dice > destination.abs_diff(field)
+            && self.validate_home(field, destination)
+        {
+            moves.push((field, destination));
+        }
+    }
+
+    fn add_moves_from_dice(&self, moves: &mut Vec<Move>, source: usize, mut dice: isize) {
+        if self.turn == WHITE {
+            dice *= -1;
+        }
+        let dest = source as isize + dice;
+        if (1..=24).contains(&dest) && self.is_move_valid(source, dest as usize) {
+            moves.push((source, dest as usize));
+        }
+    }
+
+    // single-die moves for the remaining dice
+    pub fn generate_moves(&self, dice: &[u8]) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut values = dice.to_vec();
+        values.sort();
+        values.dedup();
+
+        for &value in &values {
+            // moves from bar first
+            if self.bar[self.turn as usize] > 0 {
+                let source: usize = if self.turn == WHITE { 25 } else { 0 };
+                self.add_moves_from_dice(&mut moves, source, value as isize);
+                continue;
+            }
+
+            // typical moves
+            for source in 1..=24 {
+                if self.which_color(source) == Some(self.turn) {
+                    self.add_moves_from_dice(&mut moves, source, value as isize);
+                }
+            }
+
+            // moves to tray
+            if self.are_all_home(self.turn) {
+                let destination: usize = if self.turn == WHITE { 0 } else { 25 };
+                self.add_moves_to_tray_dice(&mut moves, destination, value as usize);
+            }
+        }
+
+        // sort and remove duplicates (needed for forced bear off)
+        moves.sort();
+        moves.dedup();
+        moves
+    }
+
+    // moves a checker and removes the used die from `dice`
+    pub fn move_checker(&mut self, source: usize, destination: usize, dice: &mut Vec<u8>) {
+        // moves to tray / other moves
+        if self.are_all_home(self.turn) && (destination == 0 || destination == 25) {
+            self.tray[self.turn as usize] += 1;
+            // removing the roll if taking of was forced (smaller move than the greatest roll)
+            if let Some((index, &max)) = dice.iter().enumerate().max_by_key(|&(_, &val)| val) {
+                let diff = destination.abs_diff(source) as u8;
+                if !dice.contains(&diff) && max > diff {
+                    dice.remove(index);
+                }
+            }
+        } else {
+            // checker gets captured
+            if let Some(color) = self.which_color(destination)
+                && color != self.turn
+            {
+                self.board[destination - 1] = Point::EMPTY;
+                self.bar[color as usize] += 1;
+            }
+            self.board[destination - 1].add(self.turn);
+        }
+
+        // moves from bar / other moves, 25 and 0 represent bar for white and black, respectively
+        let bar_source = if self.turn == WHITE { 25 } else { 0 };
+        if source == bar_source && self.bar[self.turn as usize] != 0 {
+            self.bar[self.turn as usize] -= 1;
+        } else {
+            self.board[source - 1].remove();
+        }
+
+        // removing the roll
+        if let Some(index) = dice
+            .iter()
+            .position(|&x| x == destination.abs_diff(source) as u8)
+        {
+            dice.remove(index);
+        }
+    }
+
+    // depth-first search over single-die moves, every leaf is a play that can't be extended
+    fn collect_plays(
+        &self,
+        dice: &[u8],
+        play: &mut Play,
+        dice_used: &mut Vec<u8>,
+        plays: &mut Vec<(Play, Vec<u8>)>,
+    ) {
+        let moves = if dice.is_empty() {
+            Vec::new()
+        } else {
+            self.generate_moves(dice)
+        };
+        if moves.is_empty() {
+            plays.push((play.clone(), dice_used.clone()));
+            return;
+        }
+
+        for (source, destination) in moves {
+            let mut next = *self;
+            let mut remaining = dice.to_vec();
+            next.move_checker(source, destination, &mut remaining);
+
+            let count = |dice: &[u8], die: u8| dice.iter().filter(|&&x| x == die).count();
+            let die = dice
+                .iter()
+                .copied()
+                .find(|&die| count(dice, die) > count(&remaining, die))
+                .unwrap_or(0);
+
+            play.push((source, destination));
+            dice_used.push(die);
+            next.collect_plays(&remaining, play, dice_used, plays);
+            play.pop();
+            dice_used.pop();
+        }
+    }
+
+    // every legal full play, using as many dice as possible and the larger die if just one fits
+    pub fn generate_plays(&self, dice: &[u8]) -> Vec<Play> {
+        let mut plays = Vec::new();
+        self.collect_plays(dice, &mut Vec::new(), &mut Vec::new(), &mut plays);
+
+        let max_len = plays.iter().map(|(play, _)| play.len()).max().unwrap_or(0);
+        plays.retain(|(play, _)| play.len() == max_len);
+
+        if max_len == 1
+            && let Some(&larger) = dice.iter().max()
+            && plays.iter().any(|(_, dice)| dice[0] == larger)
+        {
+            plays.retain(|(_, dice)| dice[0] == larger);
+        }
+
+        let mut plays: Vec<Play> = plays.into_iter().map(|(play, _)| play).collect();
+        plays.sort();
+        plays.dedup();
+        plays
+    }
+
+    // applies a whole play, e.g. one chosen by a bot
+    pub fn apply_play(&mut self, play: &[Move], dice: &[u8]) {
+        let mut dice = dice.to_vec();
+        for &(source, destination) in play {
+            self.move_checker(source, destination, &mut dice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::BLACK;
+
+    struct Case {
+        name: &'static str,
+        checkers: &'static [(usize, u8, u8)], // (point, color, count)
+        turn: u8,
+        bar: [u8; 2],
+        tray: [u8; 2],
+        dice: [u8; 2],
+        moves: &'static [Move], // legal first moves
+    }
+
+    const OPENING: &[(usize, u8, u8)] = &[
+        (1, BLACK, 2),
+        (6, WHITE, 5),
+        (8, WHITE, 3),
+        (12, BLACK, 5),
+        (13, WHITE, 5),
+        (17, BLACK, 3),
+        (19, BLACK, 5),
+        (24, WHITE, 2),
+    ];
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "opening 6-5",
+            checkers: OPENING,
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [6, 5],
+            moves: &[(8, 2), (8, 3), (13, 7), (13, 8), (24, 18)],
+        },
+        Case {
+            name: "black opening 6-5",
+            checkers: OPENING,
+            turn: BLACK,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [6, 5],
+            moves: &[(1, 7), (12, 17), (12, 18), (17, 22), (17, 23)],
+        },
+        Case {
+            name: "first move must leave the second die playable",
+            checkers: &[
+                (2, BLACK, 2),
+                (8, WHITE, 1),
+                (13, BLACK, 2),
+                (14, BLACK, 2),
+                (20, WHITE, 1),
+            ],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [6, 1],
+            moves: &[(8, 7)],
+        },
+        Case {
+            name: "only one die fits, the larger one",
+            checkers: &[(2, BLACK, 2), (13, WHITE, 1)],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [5, 6],
+            moves: &[(13, 7)],
+        },
+        Case {
+            name: "smaller die when the larger is blocked",
+            checkers: &[(13, BLACK, 2), (14, BLACK, 2), (20, WHITE, 1)],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [6, 1],
+            moves: &[(20, 19)],
+        },
+        Case {
+            name: "doubles use all four dice",
+            checkers: &[(24, WHITE, 1)],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [2, 2],
+            moves: &[(24, 22)],
+        },
+        Case {
+            name: "enter from the bar before anything else",
+            checkers: &[(13, WHITE, 1), (19, BLACK, 2)],
+            turn: WHITE,
+            bar: [1, 0],
+            tray: [0, 0],
+            dice: [6, 5],
+            moves: &[(25, 20)],
+        },
+        Case {
+            name: "black enters on a white blot",
+            checkers: &[(3, WHITE, 1), (4, WHITE, 2)],
+            turn: BLACK,
+            bar: [0, 1],
+            tray: [0, 0],
+            dice: [3, 4],
+            moves: &[(0, 3)],
+        },
+        Case {
+            name: "closed board",
+            checkers: &[
+                (13, WHITE, 2),
+                (19, BLACK, 2),
+                (20, BLACK, 2),
+                (21, BLACK, 2),
+                (22, BLACK, 2),
+                (23, BLACK, 2),
+                (24, BLACK, 2),
+            ],
+            turn: WHITE,
+            bar: [1, 0],
+            tray: [0, 0],
+            dice: [6, 5],
+            moves: &[],
+        },
+        Case {
+            name: "bear off with a higher roll from the farthest point",
+            checkers: &[(2, WHITE, 1), (5, WHITE, 1)],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [13, 0],
+            dice: [6, 1],
+            moves: &[(2, 1), (5, 0), (5, 4)],
+        },
+        Case {
+            name: "no higher roll bear off while a higher point is taken",
+            checkers: &[(2, WHITE, 1), (6, WHITE, 1)],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [13, 0],
+            dice: [4, 3],
+            moves: &[(6, 2), (6, 3)],
+        },
+        Case {
+            name: "no bearing off with a checker outside home",
+            checkers: &[(2, WHITE, 13), (9, WHITE, 2)],
+            turn: WHITE,
+            bar: [0, 0],
+            tray: [0, 0],
+            dice: [2, 1],
+            moves: &[(2, 1), (9, 7), (9, 8)],
+        },
+        Case {
+            name: "black bears off",
+            checkers: &[(20, BLACK, 1), (23, BLACK, 1)],
+            turn: BLACK,
+            bar: [0, 0],
+            tray: [0, 13],
+            dice: [6, 1],
+            moves: &[(20, 21), (20, 25), (23, 24)],
+        },
+    ];
+
+    fn position(case: &Case) -> Position {
+        Position::from_checkers(case.checkers, case.turn, case.bar, case.tray)
+    }
+
+    #[test]
+    fn legal_first_moves() {
+        for case in CASES {
+            let dice = dice_from_roll(case.dice[0], case.dice[1]);
+            let plays = position(case).generate_plays(&dice);
+            assert_eq!(legal_moves(&plays, &[]), case.moves, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn plays_use_both_dice() {
+        let plays = position(&CASES[2]).generate_plays(&[6, 1]);
+        assert_eq!(plays, vec![vec![(8, 7), (7, 1)]]);
+    }
+
+    #[test]
+    fn moves_follow_the_chosen_play() {
+        let mut position = Position::from_checkers(&[(24, WHITE, 1)], WHITE, [0, 0], [0, 0]);
+        let mut dice = dice_from_roll(2, 2);
+        let plays = position.generate_plays(&dice);
+        let mut played = Vec::new();
+        for (source, destination) in [(24, 22), (22, 20), (20, 18), (18, 16)] {
+            assert_eq!(legal_moves(&plays, &played), vec![(source, destination)]);
+            position.move_checker(source, destination, &mut dice);
+            played.push((source, destination));
+        }
+        assert!(legal_moves(&plays, &played).is_empty());
+        assert_eq!(position.board[15], Point::new(WHITE, 1));
+    }
+
+    #[test]
+    fn move_from_board_keeps_bar() {
+        let mut position = Position::from_checkers(&[(13, WHITE, 2)], WHITE, [1, 0], [0, 0]);
+        let mut dice = vec![6, 5];
+        position.move_checker(13, 7, &mut dice);
+        assert_eq!(position.bar, [1, 0]);
+        assert_eq!(position.board[12], Point::new(WHITE, 1));
+        assert_eq!(position.board[6], Point::new(WHITE, 1));
+        assert_eq!(dice, vec![5]);
+    }
+
+    #[test]
+    fn enter_from_bar_and_hit() {
+        let mut position = Position::from_checkers(&[(20, BLACK, 1)], WHITE, [1, 0], [0, 0]);
+        position.move_checker(25, 20, &mut vec![5, 3]);
+        assert_eq!(position.bar, [0, 1]);
+        assert_eq!(position.board[19], Point::new(WHITE, 1));
+    }
+
+    #[test]
+    fn black_hit_leaves_single_black_checker() {
+        let checkers = [(5, WHITE, 1), (2, BLACK, 1)];
+        let mut position = Position::from_checkers(&checkers, BLACK, [0, 0], [0, 0]);
+        position.move_checker(2, 5, &mut vec![3, 1]);
+        assert_eq!(position.board[4], Point::new(BLACK, 1));
+        assert_eq!(position.board[1], Point::EMPTY);
+        assert_eq!(position.bar, [1, 0]);
+    }
+
+    #[test]
+    fn bear_off_with_higher_roll_uses_larger_die() {
+        let checkers = [(2, WHITE, 1), (3, WHITE, 1)];
+        let mut position = Position::from_checkers(&checkers, WHITE, [0, 0], [13, 0]);
+        let mut dice = vec![6, 1];
+        position.move_checker(3, 0, &mut dice);
+        assert_eq!(position.tray, [14, 0]);
+        assert_eq!(dice, vec![1]);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::bot::{all_rolls, evaluate};
+use crate::position::{Position, opponent};
+use crate::rules::{Move, Play, dice_from_roll};
+use std::collections::HashSet;
+
+// Expectiminimax over whole plays. A ply is one player's play, between two plies all 21
+// rolls are averaged as a chance node. Max nodes only search the plays that look best by
+// the static evaluation, the rest are pruned.
+
+// equity of a position for `color`, +1 for a sure single win and -1 for a sure loss
+pub trait Evaluator: Sync {
+    fn equity(&self, position: &Position, color: u8) -> f64;
+
+    // cubeless chances, evaluators without a gammon estimate only give the winning chance
+    fn probabilities(&self, position: &Position, color: u8) -> Probabilities {
+        Probabilities::from_equity(self.equity(position, color))
+    }
+}
+
+// cubeless chances for one side, gammons include backgammons
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Probabilities {
+    pub win: f64,
+    pub win_gammon: f64,
+    pub lose_gammon: f64,
+}
+
+impl Probabilities {
+    pub fn from_equity(equity: f64) -> Self {
+        Self {
+            win: ((equity + 1.0) / 2.0).clamp(0.0, 1.0),
+            win_gammon: 0.0,
+            lose_gammon: 0.0,
+        }
+    }
+
+    // a finished game, `points` won by this side or lost when negative
+    pub fn certain(points: f64) -> Self {
+        Self {
+            win: (points > 0.0) as u8 as f64,
+            win_gammon: (points >= 2.0) as u8 as f64,
+            lose_gammon: (points <= -2.0) as u8 as f64,
+        }
+    }
+
+    pub fn equity(&self) -> f64 {
+        2.0 * self.win - 1.0 + self.win_gammon - self.lose_gammon
+    }
+}
+
+// bot::evaluate squashed into equity
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Heuristic;
+
+impl Evaluator for Heuristic {
+    fn equity(&self, position: &Position, color: u8) -> f64 {
+        (evaluate(position, color) / 30.0).tanh()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchConfig {
+    pub depth: u32,   // 1 only evaluates the positions after each play
+    pub width: usize, // plays searched deeper at every max node
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self { depth: 2, width: 4 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankedPlay {
+    pub play: Play,
+    pub equity: f64,
+    pub depth: u32, // plies actually searched, pruned plays only get the static evaluation
+}
+
+// finished games are worth the win multiplier, everything else comes from the evaluator
+pub fn position_equity(position: &Position, color: u8, evaluator: &dyn Evaluator) -> f64 {
+    for winner in [color, opponent(color)] {
+        if position.tray[winner as usize] == 15 {
+            let points = position.win_multiplier(winner) as f64;
+            return if winner == color { points } else { -points };
+        }
+    }
+    evaluator.equity(position, color)
+}
+
+// position_equity as chances
+pub fn position_probabilities(
+    position: &Position,
+    color: u8,
+    evaluator: &dyn Evaluator,
+) -> Probabilities {
+    if position.is_over() {
+        return Probabilities::certain(position_equity(position, color, evaluator));
+    }
+    evaluator.probabilities(position, color)
+}
+
+// legal plays for the side on turn, best first, one per resulting position
+pub fn rank_plays(
+    position: &Position,
+    dice: &[u8],
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+) -> Vec<RankedPlay> {
+    let color = position.turn;
+    let mut ranked: Vec<(RankedPlay, Position)> = position
+        .generate_plays(dice)
+        .into_iter()
+        .map(|play| {
+            let mut next = *position;
+            next.apply_play(&play, dice);
+            let equity = position_equity(&next, color, evaluator);
+            (
+                RankedPlay {
+                    play,
+                    equity,
+                    depth: 1,
+                },
+                next,
+            )
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    ranked.retain(|(_, next)| seen.insert(*next));
+    sort_best_first(&mut ranked);
+
+    if config.depth > 1 {
+        for (ranked_play, next) in ranked.iter_mut().take(config.width) {
+            ranked_play.equity = after_play(next, color, config.depth - 1, config, evaluator);
+            ranked_play.depth = config.depth;
+        }
+        // deeper searched plays first, the static rest keeps its order behind them
+        let searched = config.width.min(ranked.len());
+        sort_best_first(&mut ranked[..searched]);
+    }
+    ranked
+        .into_iter()
+        .map(|(ranked_play, _)| ranked_play)
+        .collect()
+}
+
+// equity of a single play searched to the full depth, e.g. one the ranking pruned
+pub fn play_equity(
+    position: &Position,
+    play: &[Move],
+    dice: &[u8],
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+) -> f64 {
+    let mut next = *position;
+    next.apply_play(play, dice);
+    after_play(
+        &next,
+        position.turn,
+        config.depth.saturating_sub(1),
+        config,
+        evaluator,
+    )
+}
+
+fn sort_best_first(ranked: &mut [(RankedPlay, Position)]) {
+    ranked.sort_by(|a, b| b.0.equity.total_cmp(&a.0.equity));
+}
+
+// equity for `color`, who has just moved, with `depth` plies left
+fn after_play(
+    position: &Position,
+    color: u8,
+    depth: u32,
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+) -> f64 {
+    if depth == 0 || position.is_over() {
+        return position_equity(position, color, evaluator);
+    }
+    let mut next = *position;
+    next.turn = opponent(color);
+    all_rolls()
+        .into_iter()
+        .map(|(dice_1, dice_2, chance)| {
+            let dice = dice_from_roll(dice_1, dice_2);
+            chance * -best_play(&next, &dice, depth, config, evaluator)
+        })
+        .sum()
+}
+
+// equity of the best play for the side on turn
+fn best_play(
+    position: &Position,
+    dice: &[u8],
+    depth: u32,
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+) -> f64 {
+    let color = position.turn;
+    let mut candidates: Vec<(f64, Position)> = position
+        .generate_plays(dice)
+        .into_iter()
+        .map(|play| {
+            let mut next = *position;
+            next.apply_play(&play, dice);
+            (position_equity(&next, color, evaluator), next)
+        })
+        .collect();
+    if depth == 1 {
+        return candidates
+            .iter()
+            .map(|(equity, _)| *equity)
+            .fold(f64::MIN, f64::max);
+    }
+
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    candidates
+        .iter()
+        .take(config.width)
+        .map(|(_, next)| after_play(next, color, depth - 1, config, evaluator))
+        .fold(f64::MIN, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{BLACK, WHITE};
+
+    #[test]
+    fn ranks_every_play_best_first() {
+        let position = Position::new();
+        let dice = dice_from_roll(6, 5);
+        let config = SearchConfig { depth: 2, width: 3 };
+        let ranked = rank_plays(&position, &dice, &config, &Heuristic);
+        let mut positions: Vec<Position> = position
+            .generate_plays(&dice)
+            .iter()
+            .map(|play| {
+                let mut next = position;
+                next.apply_play(play, &dice);
+                next
+            })
+            .collect();
+        positions.sort_by_key(|next| next.board_values());
+        positions.dedup();
+        assert!(ranked.len() < position.generate_plays(&dice).len());
+        assert_eq!(ranked.len(), positions.len());
+        assert!(ranked[..3].iter().all(|ranked| ranked.depth == 2));
+        assert!(ranked[3..].iter().all(|ranked| ranked.depth == 1));
+        assert!(ranked[..3].windows(2).all(|w| w[0].equity >= w[1].equity));
+        assert!(ranked[3..].windows(2).all(|w| w[0].equity >= w[1].equity));
+    }
+
+    #[test]
+    fn one_ply_matches_greedy_bot() {
+        let position = Position::new();
+        let dice = dice_from_roll(3, 1);
+        let config = SearchConfig { depth: 1, width: 6 };
+        let ranked = rank_plays(&position, &dice, &config, &Heuristic);
+        let mut best = ranked[0].play.clone();
+        best.sort();
+        assert_eq!(best, vec![(6, 5), (8, 5)]);
+    }
+
+    #[test]
+    fn bearing_off_last_checkers_wins_gammon() {
+        let position =
+            Position::from_checkers(&[(1, WHITE, 2), (12, BLACK, 15)], WHITE, [0, 0], [13, 0]);
+        let ranked = rank_plays(
+            &position,
+            &dice_from_roll(2, 1),
+            &SearchConfig::default(),
+            &Heuristic,
+        );
+        assert_eq!(ranked[0].equity, 2.0);
+    }
+
+    #[test]
+    fn chance_node_averages_opponent_rolls() {
+        // white bears off with every roll, black has borne off so it's a single game
+        let position =
+            Position::from_checkers(&[(2, WHITE, 1), (20, BLACK, 1)], BLACK, [0, 0], [14, 14]);
+        let config = SearchConfig::default();
+        let equity = after_play(&position, BLACK, 1, &config, &Heuristic);
+        assert!((equity + 1.0).abs() < 1e-9);
+
+        // only doubles of three and up get white's checker on 12 off, black is still in it
+        let position =
+            Position::from_checkers(&[(12, WHITE, 1), (20, BLACK, 1)], BLACK, [0, 0], [14, 14]);
+        let equity = after_play(&position, BLACK, 1, &config, &Heuristic);
+        assert!(equity > -1.0 && equity < 1.0);
+    }
+}
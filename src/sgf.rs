@@ -0,0 +1,558 @@
+use crate::cube;
+use crate::mat::MatGame;
+use crate::notation;
+use crate::position::{BLACK, Position, WHITE};
+use crate::replay::{Action, Record, Replay, Snapshot};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Smart Game Format files for backgammon (GM[6]), GNU Backgammon's own match format. Every
+// game is a tree of nodes: the first one holds the game info, the others one action each:
+//
+//   (;FF[4]GM[6]CA[UTF-8]MI[length:5][game:0][ws:0][bs:0]PW[Alice]PB[Bob]RE[W+1]
+//   ;W[31qtst]
+//   ;B[double]C[a comment]BM[1]
+//   ;W[drop])
+//
+// Points are letters counted from black's 1-point, 'a', to white's 1-point, 'x', for both
+// sides, 'y' is the bar and 'z' off. A game that doesn't start from the opening position
+// gets setup properties: AE[a:y] to empty the board and a letter per checker in AW and AB.
+
+const APPLICATION: &str = "cli_backgammon";
+
+// the marks SGF puts on moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    VeryBad,     // BM[2]
+    Bad,         // BM[1]
+    Doubtful,    // DO[]
+    Interesting, // IT[]
+    Good,        // TE[1]
+    VeryGood,    // TE[2]
+}
+
+impl Mark {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mark::VeryBad => "very bad",
+            Mark::Bad => "bad",
+            Mark::Doubtful => "doubtful",
+            Mark::Interesting => "interesting",
+            Mark::Good => "good",
+            Mark::VeryGood => "very good",
+        }
+    }
+
+    fn property(&self) -> &'static str {
+        match self {
+            Mark::VeryBad => "BM[2]",
+            Mark::Bad => "BM[1]",
+            Mark::Doubtful => "DO[]",
+            Mark::Interesting => "IT[]",
+            Mark::Good => "TE[1]",
+            Mark::VeryGood => "TE[2]",
+        }
+    }
+
+    fn parse(name: &str, value: &str) -> Option<Self> {
+        match (name, value) {
+            ("BM", "2") => Some(Mark::VeryBad),
+            ("BM", _) => Some(Mark::Bad),
+            ("DO", _) => Some(Mark::Doubtful),
+            ("IT", _) => Some(Mark::Interesting),
+            ("TE", "2") => Some(Mark::VeryGood),
+            ("TE", _) => Some(Mark::Good),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Annotation {
+    pub mark: Option<Mark>,
+    pub comment: String,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.mark.is_none() && self.comment.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SgfGame {
+    pub score: [u32; 2], // before the game
+    pub replay: Replay,
+    pub annotations: Vec<Annotation>, // one per record, missing ones are empty
+}
+
+impl From<MatGame> for SgfGame {
+    fn from(game: MatGame) -> Self {
+        SgfGame {
+            score: game.score,
+            replay: game.replay,
+            annotations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collection {
+    pub names: [String; 2],
+    pub length: u32, // 0 for money sessions
+    pub games: Vec<SgfGame>,
+}
+
+type Property = (String, Vec<String>);
+type Node = Vec<Property>;
+
+fn letter(field: usize, color: u8) -> char {
+    match notation::point(field, color) {
+        25 => 'y',
+        0 => 'z',
+        _ => (b'a' + 24 - field as u8) as char,
+    }
+}
+
+fn field(letter: u8, color: u8) -> Option<usize> {
+    match letter {
+        b'a'..=b'x' => Some(24 - (letter - b'a') as usize),
+        b'y' => Some(notation::point(25, color)),
+        b'z' => Some(notation::point(0, color)),
+        _ => None,
+    }
+}
+
+fn side(color: u8) -> char {
+    if color == WHITE { 'W' } else { 'B' }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(']', "\\]")
+}
+
+fn write_setup(f: &mut fmt::Formatter, start: &Snapshot) -> fmt::Result {
+    let mut opening = Position::new();
+    opening.turn = start.position.turn;
+    if start.position != opening {
+        write!(f, "AE[a:y]")?;
+        let position = &start.position;
+        for color in [WHITE, BLACK] {
+            let mut checkers = String::new();
+            for field in 1..=24 {
+                if position.which_color(field) == Some(color) {
+                    let count = position.checker_count(field) as usize;
+                    checkers += &format!("[{}]", letter(field, color)).repeat(count);
+                }
+            }
+            let bar = notation::point(25, color);
+            let count = position.bar[color as usize] as usize;
+            checkers += &format!("[{}]", letter(bar, color)).repeat(count);
+            if !checkers.is_empty() {
+                write!(f, "A{}{checkers}", side(color))?;
+            }
+        }
+        write!(f, "PL[{}]", side(start.position.turn))?;
+    }
+    if start.cube_value != 1 || start.cube_owner.is_some() {
+        let owner = start
+            .cube_owner
+            .map_or('c', |color| side(color).to_ascii_lowercase());
+        write!(f, "CV[{}]CP[{owner}]", start.cube_value)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Collection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (number, game) in self.games.iter().enumerate() {
+            write!(f, "(;FF[4]GM[6]CA[UTF-8]AP[{APPLICATION}]")?;
+            write!(
+                f,
+                "MI[length:{}][game:{number}][ws:{}][bs:{}]",
+                self.length, game.score[0], game.score[1]
+            )?;
+            let [white, black] = &self.names;
+            write!(f, "PW[{}]PB[{}]", escape(white), escape(black))?;
+            if let Some((winner, points)) = game.replay.result() {
+                write!(f, "RE[{}+{points}]", side(winner))?;
+            }
+            write_setup(f, &game.replay.start)?;
+            writeln!(f)?;
+
+            for (index, record) in game.replay.records.iter().enumerate() {
+                write!(f, ";{}[", side(record.color))?;
+                match &record.action {
+                    Action::Roll((die_1, die_2), play) => {
+                        write!(f, "{die_1}{die_2}")?;
+                        for &(source, destination) in play {
+                            let source = letter(source, record.color);
+                            write!(f, "{source}{}", letter(destination, record.color))?;
+                        }
+                    }
+                    Action::Double => write!(f, "double")?,
+                    Action::Take => write!(f, "take")?,
+                    Action::Pass => write!(f, "drop")?,
+                }
+                write!(f, "]")?;
+                if let Some(annotation) = game.annotations.get(index) {
+                    if let Some(mark) = annotation.mark {
+                        write!(f, "{}", mark.property())?;
+                    }
+                    if !annotation.comment.is_empty() {
+                        write!(f, "C[{}]", escape(&annotation.comment))?;
+                    }
+                }
+                writeln!(f)?;
+            }
+            writeln!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Reader<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    // "[...]" with "\]" and "\\" escaped, a backslash before a line break removes it
+    fn value(&mut self) -> Option<String> {
+        let mut value = String::new();
+        loop {
+            match self.chars.next()? {
+                ']' => return Some(value),
+                '\\' => match self.chars.next()? {
+                    '\n' => {}
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Option<Node> {
+        let mut node = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let mut name = String::new();
+            while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+                // FF[3] allowed small letters in names, they don't count
+                if c.is_ascii_uppercase() {
+                    name.push(c);
+                }
+            }
+            if name.is_empty() {
+                return Some(node);
+            }
+            let mut values = Vec::new();
+            self.skip_whitespace();
+            while self.chars.next_if_eq(&'[').is_some() {
+                values.push(self.value()?);
+                self.skip_whitespace();
+            }
+            if values.is_empty() {
+                return None;
+            }
+            node.push((name, values));
+        }
+    }
+
+    // the nodes of a tree after its "(", only the first variation is followed
+    fn tree(&mut self) -> Option<Vec<Node>> {
+        let mut nodes = Vec::new();
+        let mut has_variation = false;
+        loop {
+            self.skip_whitespace();
+            match self.chars.next()? {
+                ';' if !has_variation => nodes.push(self.node()?),
+                '(' => {
+                    let variation = self.tree()?;
+                    if !has_variation {
+                        nodes.extend(variation);
+                        has_variation = true;
+                    }
+                }
+                ')' => return Some(nodes),
+                _ => return None,
+            }
+        }
+    }
+}
+
+fn values<'a>(node: &'a Node, name: &str) -> &'a [String] {
+    node.iter()
+        .find(|(other, _)| other == name)
+        .map_or(&[], |(_, values)| values)
+}
+
+// "a:y" is every point from a to y
+fn expand_points(values: &[String]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| match value.as_bytes() {
+            [from, b':', to] => (*from..=*to).collect(),
+            bytes => bytes.to_vec(),
+        })
+        .collect()
+}
+
+fn setup(node: &Node, start: &mut Snapshot) -> Option<()> {
+    let position = &mut start.position;
+    // checkers that aren't on the board or the bar are borne off
+    position.tray = [0, 0];
+    for letter in expand_points(values(node, "AE")) {
+        for color in [WHITE, BLACK] {
+            while position.remove_checker(field(letter, color)?, color) {}
+        }
+    }
+    for (name, color) in [("AW", WHITE), ("AB", BLACK)] {
+        for letter in expand_points(values(node, name)) {
+            if !position.place_checker(field(letter, color)?, color) {
+                return None;
+            }
+        }
+    }
+    for color in [WHITE, BLACK] {
+        position.tray[color as usize] = 15 - position.checker_total(color) as u8;
+    }
+    match values(node, "PL").first().map(String::as_str) {
+        Some("W") => position.turn = WHITE,
+        Some("B") => position.turn = BLACK,
+        _ => {}
+    }
+    if let Some(value) = values(node, "CV").first() {
+        start.cube_value = value
+            .parse()
+            .ok()
+            .filter(|&value| cube::is_valid_cube_value(value))?;
+    }
+    match values(node, "CP").first().map(String::as_str) {
+        Some("w") => start.cube_owner = Some(WHITE),
+        Some("b") => start.cube_owner = Some(BLACK),
+        Some(_) => start.cube_owner = None,
+        None => {}
+    }
+    Some(())
+}
+
+fn parse_action(value: &str, color: u8) -> Option<Action> {
+    match value {
+        "double" => Some(Action::Double),
+        "take" => Some(Action::Take),
+        "drop" | "pass" => Some(Action::Pass),
+        _ => {
+            let bytes = value.as_bytes();
+            let [die_1 @ b'1'..=b'6', die_2 @ b'1'..=b'6', moves @ ..] = bytes else {
+                return None;
+            };
+            if moves.len() % 2 != 0 {
+                return None;
+            }
+            let play = moves
+                .chunks(2)
+                .map(|pair| Some((field(pair[0], color)?, field(pair[1], color)?)))
+                .collect::<Option<Vec<_>>>()?;
+            Some(Action::Roll((die_1 - b'0', die_2 - b'0'), play))
+        }
+    }
+}
+
+fn parse_game(nodes: &[Node], names: &mut [String; 2], length: &mut u32) -> Option<SgfGame> {
+    let root = nodes.first()?;
+    if values(root, "GM").first().is_some_and(|game| game != "6") {
+        return None;
+    }
+    for (name, color) in [("PW", WHITE), ("PB", BLACK)] {
+        if let Some(value) = values(root, name).first() {
+            names[color as usize] = value.clone();
+        }
+    }
+    let mut score = [0, 0];
+    for info in values(root, "MI") {
+        let Some((key, value)) = info.split_once(':') else {
+            continue;
+        };
+        match key {
+            "length" => *length = value.parse().ok()?,
+            "ws" => score[WHITE as usize] = value.parse().ok()?,
+            "bs" => score[BLACK as usize] = value.parse().ok()?,
+            _ => {}
+        }
+    }
+
+    let mut start = Snapshot {
+        position: Position::new(),
+        cube_value: 1,
+        cube_owner: None,
+    };
+    let mut records = Vec::new();
+    let mut annotations = Vec::new();
+    let mut has_turn = false;
+    for node in nodes {
+        let Some((color, value)) = [(WHITE, "W"), (BLACK, "B")]
+            .into_iter()
+            .find_map(|(color, name)| Some((color, values(node, name).first()?)))
+        else {
+            // setup before the first action
+            if records.is_empty() {
+                setup(node, &mut start)?;
+                has_turn |= !values(node, "PL").is_empty();
+            }
+            continue;
+        };
+        if records.is_empty() && !has_turn {
+            start.position.turn = color;
+        }
+        records.push(Record {
+            color,
+            action: parse_action(value, color)?,
+        });
+        annotations.push(Annotation {
+            mark: ["BM", "DO", "IT", "TE"]
+                .into_iter()
+                .find_map(|name| Mark::parse(name, values(node, name).first()?)),
+            comment: values(node, "C").join("\n"),
+        });
+    }
+    let replay = Replay {
+        start,
+        tags: Vec::new(),
+        records,
+    };
+    replay.rebuild()?;
+    Some(SgfGame {
+        score,
+        replay,
+        annotations,
+    })
+}
+
+impl Collection {
+    // None when there's no backgammon game tree or a move can't be made
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut reader = Reader {
+            chars: content.chars().peekable(),
+        };
+        let mut names = ["White".to_string(), "Black".to_string()];
+        let mut length = 0;
+        let mut games = Vec::new();
+        while reader.chars.find(|&c| c == '(').is_some() {
+            let nodes = reader.tree()?;
+            games.push(parse_game(&nodes, &mut names, &mut length)?);
+        }
+        if games.is_empty() {
+            return None;
+        }
+        Some(Self {
+            names,
+            length,
+            games,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(color: u8, action: Action) -> Record {
+        Record { color, action }
+    }
+
+    #[test]
+    fn writes_and_reads_games() {
+        let records = vec![
+            record(WHITE, Action::Roll((3, 1), vec![(8, 5), (6, 5)])),
+            record(BLACK, Action::Roll((6, 4), vec![(1, 7), (12, 16)])),
+            record(WHITE, Action::Double),
+            record(BLACK, Action::Take),
+            record(
+                WHITE,
+                Action::Roll((6, 6), vec![(24, 18), (24, 18), (13, 7), (13, 7)]),
+            ),
+            record(BLACK, Action::Double),
+            record(WHITE, Action::Pass),
+        ];
+        let mut annotations = vec![Annotation::default(); records.len()];
+        annotations[1] = Annotation {
+            mark: Some(Mark::VeryBad),
+            comment: "best: 24/18 13/9 [sic]\nsecond line".to_string(),
+        };
+        let mut start = Snapshot {
+            position: Position::new(),
+            cube_value: 1,
+            cube_owner: None,
+        };
+        let first = SgfGame {
+            score: [0, 0],
+            replay: Replay {
+                start,
+                tags: Vec::new(),
+                records,
+            },
+            annotations,
+        };
+        // a bear-off with the cube on 2 and a white checker on the bar
+        start.position =
+            Position::from_checkers(&[(3, WHITE, 2), (22, BLACK, 3)], BLACK, [1, 0], [12, 12]);
+        start.cube_value = 2;
+        start.cube_owner = Some(WHITE);
+        let second = SgfGame {
+            score: [0, 2],
+            replay: Replay {
+                start,
+                tags: Vec::new(),
+                records: vec![record(
+                    BLACK,
+                    Action::Roll((3, 2), vec![(22, 25), (22, 24)]),
+                )],
+            },
+            annotations: vec![Annotation::default()],
+        };
+        let collection = Collection {
+            names: ["Alice".to_string(), "Bob".to_string()],
+            length: 3,
+            games: vec![first, second],
+        };
+        let text = collection.to_string();
+        assert!(text.starts_with("(;FF[4]GM[6]CA[UTF-8]"));
+        assert!(text.contains("MI[length:3][game:0][ws:0][bs:0]PW[Alice]PB[Bob]RE[B+2]\n"));
+        assert!(text.contains("\n;W[31qtst]\n;B[64xrmi]BM[2]C[best: 24/18 13/9 [sic\\]"));
+        assert!(text.contains("\n;W[double]\n;B[take]\n;W[66agaglrlr]\n;B[double]\n;W[drop]\n)\n"));
+        assert!(text.contains("AE[a:y]AW[v][v][y]AB[c][c][c]PL[B]CV[2]CP[w]\n;B[32czca]\n"));
+        assert_eq!(Collection::parse(&text), Some(collection));
+        assert_eq!(Collection::parse(&text.replace("CV[2]", "CV[0]")), None);
+        assert_eq!(Collection::parse(&text.replace("CV[2]", "CV[3]")), None);
+    }
+
+    #[test]
+    fn reads_gnubg_files() {
+        let text = "(;FF[4]GM[6]CA[UTF-8]AP[GNU Backgammon:1.07.001]RU[Crawford]\n\
+                    MI[length:1][game:0][ws:0][bs:0][wtime:0][btime:0]PW[gnubg]PB[user]\n\
+                    ;B[43xtxu]A[ver:3][43:2][mxtxu]LU[0.1]\n\
+                    ;W[64aglp]C[split\\]\n]DO[]\n\
+                    (;B[31hefe]TE[2])(;B[31hefd]))";
+        let collection = Collection::parse(text).unwrap();
+        assert_eq!(collection.names, ["gnubg".to_string(), "user".to_string()]);
+        assert_eq!(collection.length, 1);
+        let game = &collection.games[0];
+        assert_eq!(game.replay.start.position.turn, BLACK);
+        assert_eq!(
+            game.replay.records[0],
+            record(BLACK, Action::Roll((4, 3), vec![(1, 5), (1, 4)]))
+        );
+        assert_eq!(game.annotations[1].comment, "split]\n");
+        assert_eq!(game.annotations[1].mark, Some(Mark::Doubtful));
+        // only the first variation is followed
+        assert_eq!(game.replay.records.len(), 3);
+        assert_eq!(game.annotations[2].mark, Some(Mark::VeryGood));
+        assert_eq!(Collection::parse("(;GM[1];B[aa])"), None);
+        assert_eq!(Collection::parse("(;GM[6];W[43xxxx])"), None);
+    }
+}
@@ -1,8 +1,8 @@
 use crossterm::{
-    queue,
     QueueableCommand,
     cursor::MoveTo,
-    style::Print,
+    queue,
+    style::{Attribute, Print, SetAttribute},
     terminal::{Clear, ClearType},
 };
 use std::io::{Write, stdout};
@@ -35,6 +35,20 @@ pub fn print_at(x: u16, y: u16, message: &str) {
     stdout.flush().unwrap();
 }
 
+// reverse video, e.g. to point out the fields of a suggested play
+pub fn print_highlighted(x: u16, y: u16, message: &str) {
+    let mut stdout = stdout();
+    queue!(
+        stdout,
+        MoveTo(x, y),
+        SetAttribute(Attribute::Reverse),
+        Print(message),
+        SetAttribute(Attribute::Reset)
+    )
+    .unwrap();
+    stdout.flush().unwrap();
+}
+
 pub fn print_message(x: u16, y: u16, message: &str) {
     clear_line(y);
     move_cursor(x, y);
@@ -0,0 +1,215 @@
+use crate::gnubg::{self, MatchId};
+use crate::position::{BLACK, Position, WHITE};
+
+// eXtreme Gammon IDs, e.g. "XGID=-b----E-C---eE---c-e----B-:0:0:1:00:0:0:0:0:10" for the
+// opening position with white to roll. Fields after the position: cube as a power of two,
+// cube owner (1 white, -1 black, 0 centered), side on roll (1 white, -1 black), dice ("00"
+// before rolling, "D" while a double is offered), white's score, black's score, Crawford
+// game (the Jacoby rule in money games), match length (0 for money) and the cube limit.
+// Offered doubles can't be set up here, so IDs with "D" aren't read.
+//
+// The position has 26 characters: black's bar, points 1 to 24 and white's bar, so the
+// index is our field number. Capital letters count white checkers (A = 1), small ones black.
+
+const PREFIX: &str = "XGID=";
+const MAX_CUBE_POWER: u32 = 10;
+
+fn side(color: u8) -> i32 {
+    if color == WHITE { 1 } else { -1 }
+}
+
+pub fn encode(position: &Position, state: &MatchId) -> String {
+    let mut board = String::new();
+    for field in 0..=25 {
+        let (color, count) = match field {
+            0 => (BLACK, position.bar[BLACK as usize]),
+            25 => (WHITE, position.bar[WHITE as usize]),
+            _ => match position.which_color(field) {
+                Some(color) => (color, position.checker_count(field)),
+                None => (WHITE, 0),
+            },
+        };
+        board.push(match (count, color) {
+            (0, _) => '-',
+            (_, WHITE) => (b'A' + count - 1) as char,
+            _ => (b'a' + count - 1) as char,
+        });
+    }
+    let dice = state.dice.map_or("00".to_string(), |(dice_1, dice_2)| {
+        format!("{dice_1}{dice_2}")
+    });
+    format!(
+        "{PREFIX}{board}:{}:{}:{}:{dice}:{}:{}:{}:{}:{MAX_CUBE_POWER}",
+        state.cube_value.max(1).trailing_zeros(),
+        state.cube_owner.map_or(0, side),
+        side(position.turn),
+        state.score[WHITE as usize],
+        state.score[BLACK as usize],
+        state.is_crawford as u8,
+        state.match_length
+    )
+}
+
+// the "XGID=" prefix is optional
+pub fn parse(text: &str) -> Option<(Position, MatchId)> {
+    let text = text.trim();
+    let fields: Vec<&str> = text
+        .strip_prefix(PREFIX)
+        .unwrap_or(text)
+        .split(':')
+        .collect();
+    let [
+        board,
+        cube,
+        owner,
+        turn,
+        dice,
+        white,
+        black,
+        crawford,
+        length,
+        ..,
+    ] = fields[..]
+    else {
+        return None;
+    };
+    if board.len() != 26 {
+        return None;
+    }
+    let color = |value: &str| match value {
+        "1" => Some(WHITE),
+        "-1" => Some(BLACK),
+        _ => None,
+    };
+    let turn = color(turn)?;
+    let mut position = Position::from_checkers(&[], turn, [0, 0], [0, 0]);
+    for (field, c) in board.chars().enumerate() {
+        let (color, count) = match c {
+            '-' => continue,
+            'A'..='O' => (WHITE, c as u8 - b'A' + 1),
+            'a'..='o' => (BLACK, c as u8 - b'a' + 1),
+            _ => return None,
+        };
+        // the ends are only bars, never trays
+        if (field == 0 && color == WHITE) || (field == 25 && color == BLACK) {
+            return None;
+        }
+        for _ in 0..count {
+            if !position.place_checker(field, color) {
+                return None;
+            }
+        }
+    }
+    for color in [WHITE, BLACK] {
+        position.tray[color as usize] = 15 - position.checker_total(color) as u8;
+    }
+
+    let power: u32 = cube.parse().ok().filter(|&power| power <= MAX_CUBE_POWER)?;
+    let cube_owner = match owner {
+        "0" => None,
+        _ => Some(color(owner)?),
+    };
+    let dice = match dice.as_bytes() {
+        [dice_1 @ b'1'..=b'6', dice_2 @ b'1'..=b'6'] => Some((dice_1 - b'0', dice_2 - b'0')),
+        b"00" => None,
+        _ => return None,
+    };
+    let match_length = length.parse().ok()?;
+    let score = [white.parse().ok()?, black.parse().ok()?];
+    if !gnubg::is_valid_score(match_length, score) {
+        return None;
+    }
+    let state = MatchId {
+        cube_value: 1 << power,
+        cube_owner,
+        on_roll: turn,
+        is_crawford: match_length > 0 && crawford == "1",
+        dice,
+        match_length,
+        score,
+    };
+    Some((position, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPENING: &str = "XGID=-b----E-C---eE---c-e----B-:0:0:1:00:0:0:0:0:10";
+
+    fn money() -> MatchId {
+        MatchId {
+            cube_value: 1,
+            cube_owner: None,
+            on_roll: WHITE,
+            is_crawford: false,
+            dice: None,
+            match_length: 0,
+            score: [0, 0],
+        }
+    }
+
+    #[test]
+    fn opening_reference_id() {
+        assert_eq!(encode(&Position::new(), &money()), OPENING);
+        assert_eq!(parse(OPENING), Some((Position::new(), money())));
+        assert!(parse("-b----E-C---eE---c-e----B-:0:0:1:00:0:0:0:0:10").is_some());
+        assert_eq!(parse("XGID=-b----E-C---eE---c-e----B-:0:0:1"), None);
+        // sixteen white checkers
+        assert_eq!(
+            parse("XGID=-b----F-C---eE---c-e----B-:0:0:1:00:0:0:0:0:10"),
+            None
+        );
+    }
+
+    #[test]
+    fn match_state_round_trip() {
+        let position = Position::from_checkers(
+            &[(2, WHITE, 4), (5, WHITE, 9), (23, BLACK, 12)],
+            BLACK,
+            [1, 2],
+            [1, 1],
+        );
+        let state = MatchId {
+            cube_value: 4,
+            cube_owner: Some(WHITE),
+            on_roll: BLACK,
+            is_crawford: true,
+            dice: Some((6, 3)),
+            match_length: 7,
+            score: [6, 3],
+        };
+        let id = encode(&position, &state);
+        assert_eq!(id, "XGID=b-D--I-----------------l-A:2:1:-1:63:6:3:1:7:10");
+        assert_eq!(parse(&id), Some((position, state)));
+    }
+
+    #[test]
+    fn money_game_jacoby_flag_is_not_crawford() {
+        let jacoby = "XGID=-b----E-C---eE---c-e----B-:0:0:1:00:0:0:1:0:10";
+        assert_eq!(parse(jacoby), Some((Position::new(), money())));
+        // a double being offered
+        assert_eq!(
+            parse("XGID=-b----E-C---eE---c-e----B-:0:0:1:D:0:0:0:0:10"),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_scores_not_below_the_match_length() {
+        assert!(parse("XGID=-b----E-C---eE---c-e----B-:0:0:1:00:4:6:0:7:10").is_some());
+        assert_eq!(
+            parse("XGID=-b----E-C---eE---c-e----B-:0:0:1:00:7:0:0:7:10"),
+            None
+        );
+        assert_eq!(
+            parse("XGID=-b----E-C---eE---c-e----B-:0:0:1:00:0:9:0:7:10"),
+            None
+        );
+        // money games have no score
+        assert_eq!(
+            parse("XGID=-b----E-C---eE---c-e----B-:0:0:1:00:2:0:0:0:10"),
+            None
+        );
+    }
+}